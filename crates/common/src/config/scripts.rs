@@ -9,9 +9,12 @@ use nlp::bayes::cache::BayesTokenCache;
 use parking_lot::RwLock;
 use sieve::{compiler::grammar::Capability, Compiler, Runtime, Sieve};
 use store::Stores;
-use utils::config::Config;
+use utils::config::{Config, Rate};
 
-use crate::scripts::{functions::register_functions, plugins::RegisterSievePlugins};
+use crate::scripts::{
+    functions::{header::is_header_name, register_functions},
+    plugins::RegisterSievePlugins,
+};
 
 use super::{if_block::IfBlock, smtp::SMTP_RCPT_TO_VARS, tokenizer::TokenMap};
 
@@ -25,6 +28,7 @@ pub struct Scripting {
     pub sign: IfBlock,
     pub scripts: AHashMap<String, Arc<Sieve>>,
     pub bayes_cache: BayesTokenCache,
+    pub bayes_learn_rate: Option<Rate>,
     pub remote_lists: RwLock<AHashMap<String, RemoteList>>,
 }
 
@@ -293,6 +297,41 @@ impl Scripting {
             }
         }
 
+        // The spam filter skips invalid header names when adding its headers
+        for key in [
+            "lookup.spam-config.header-status",
+            "lookup.spam-config.header-result",
+        ] {
+            let invalid = config
+                .value(key)
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty() && !is_header_name(name))
+                .map(|name| format!("{name:?}"))
+                .collect::<Vec<_>>();
+            if !invalid.is_empty() {
+                config.new_build_warning(
+                    key,
+                    format!(
+                        "Invalid header names {}, these headers will not be added",
+                        invalid.join(", ")
+                    ),
+                );
+            }
+        }
+
+        // The spam filter ignores score precisions it cannot apply
+        let key = "lookup.spam-config.score-precision";
+        if let Some(value) = config.value(key).filter(|v| !v.trim().is_empty()) {
+            if !value.trim().parse::<u8>().is_ok_and(|p| p <= 6) {
+                let error = format!(
+                    "Invalid score precision {value:?}, expected a number between 0 and 6, scores will not be rounded"
+                );
+                config.new_build_warning(key, error);
+            }
+        }
+
         let token_map = TokenMap::default().with_variables(SMTP_RCPT_TO_VARS);
 
         Scripting {
@@ -341,6 +380,10 @@ impl Scripting {
                     .property_or_default("cache.bayes.ttl.negative", "1h")
                     .unwrap_or_else(|| Duration::from_secs(3600)),
             ),
+            bayes_learn_rate: config
+                .property::<Option<Rate>>("spam.bayes.auto-learn-rate")
+                .filter(|v| v.as_ref().is_some_and(|r| r.requests > 0))
+                .unwrap_or_default(),
             remote_lists: Default::default(),
         }
     }
@@ -370,6 +413,7 @@ impl Default for Scripting {
                 Duration::from_secs(3600),
                 Duration::from_secs(3600),
             ),
+            bayes_learn_rate: None,
             remote_lists: Default::default(),
         }
     }
@@ -387,6 +431,7 @@ impl Clone for Scripting {
             sign: self.sign.clone(),
             scripts: self.scripts.clone(),
             bayes_cache: self.bayes_cache.clone(),
+            bayes_learn_rate: self.bayes_learn_rate.clone(),
             remote_lists: RwLock::new(self.remote_lists.read().clone()),
         }
     }
@@ -21,28 +21,35 @@
  * for more details.
 */
 
-use std::{net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use rustls::{
     crypto::ring::{default_provider, ALL_CIPHER_SUITES},
     ServerConfig, SupportedCipherSuite, ALL_VERSIONS,
 };
 
+use socket2::SockRef;
 use tokio::net::TcpSocket;
 use tokio_rustls::TlsAcceptor;
 use utils::config::{
+    ipmask::IpAddrMask,
     utils::{AsKey, ParseValue},
-    Config,
+    Config, ConfigError, Rate,
 };
 
 use crate::{
-    listener::{tls::CertificateResolver, TcpAcceptor},
+    listener::{ocsp::OcspStapler, tls::CertificateResolver, TcpAcceptor},
     SharedCore,
 };
 
 use super::{
-    tls::{TLS12_VERSION, TLS13_VERSION},
-    Listener, Server, ServerProtocol, Servers,
+    tls::{parse_sni_certificates, TLS12_VERSION, TLS13_VERSION},
+    Listener, ListenerSettings, Server, ServerProtocol, Servers, UnixSocketListener,
 };
 
 impl Servers {
@@ -56,7 +63,9 @@ impl Servers {
             .map(|s| s.to_string())
             .collect::<Vec<_>>()
         {
-            servers.parse_server(config, id);
+            if is_enabled(config, &id) {
+                servers.parse_server(config, id);
+            }
         }
         servers
     }
@@ -72,9 +81,16 @@ impl Servers {
             };
 
         // Build listeners
+        let settings = ListenerSettings::parse(config, id);
         let mut listeners = Vec::new();
-        for (_, addr) in config.properties::<SocketAddr>(("server.listener", id, "bind")) {
-            // Parse bind address and build socket
+        let mut unix_listeners = Vec::new();
+        for path in &settings.unix_bind {
+            if let Some(listener) = build_unix_listener(config, id, path, &settings) {
+                unix_listeners.push(listener);
+            }
+        }
+        for &addr in &settings.bind {
+            // Build socket
             let socket = match if addr.is_ipv4() {
                 TcpSocket::new_v4()
             } else {
@@ -91,111 +107,132 @@ impl Servers {
             };
 
             // Set socket options
-            for option in [
-                "reuse-addr",
-                "reuse-port",
-                "send-buffer-size",
-                "recv-buffer-size",
-                "tos",
+            for (option, result) in [
+                (
+                    "reuse-addr",
+                    Some(socket.set_reuseaddr(settings.reuse_addr)),
+                ),
+                (
+                    "send-buffer-size",
+                    settings
+                        .send_buffer_size
+                        .map(|size| socket.set_send_buffer_size(size)),
+                ),
+                (
+                    "recv-buffer-size",
+                    settings
+                        .recv_buffer_size
+                        .map(|size| socket.set_recv_buffer_size(size)),
+                ),
             ] {
-                if let Some(value) = config.value_or_else(
-                    ("server.listener", id, "socket", option),
-                    ("server.socket", option),
-                ) {
-                    let value = value.to_string();
-                    let key = ("server.listener", id, "socket", option);
-                    let result = match option {
-                        "reuse-addr" => socket
-                            .set_reuseaddr(config.try_parse_value(key, &value).unwrap_or(true)),
-                        #[cfg(not(target_env = "msvc"))]
-                        "reuse-port" => socket
-                            .set_reuseport(config.try_parse_value(key, &value).unwrap_or(false)),
-                        "send-buffer-size" => {
-                            if let Some(value) = config.try_parse_value(key, &value) {
-                                socket.set_send_buffer_size(value)
-                            } else {
-                                continue;
-                            }
-                        }
-                        "recv-buffer-size" => {
-                            if let Some(value) = config.try_parse_value(key, &value) {
-                                socket.set_recv_buffer_size(value)
-                            } else {
-                                continue;
-                            }
-                        }
-                        "tos" => {
-                            if let Some(value) = config.try_parse_value(key, &value) {
-                                socket.set_tos(value)
-                            } else {
-                                continue;
-                            }
-                        }
-                        _ => continue,
-                    };
-
-                    if let Err(err) = result {
-                        config.new_build_error(key, format!("Failed to set socket option: {err}"));
-                    }
+                if let Some(Err(err)) = result {
+                    config.new_build_error(
+                        ("server.listener", id, "socket", option),
+                        format!("Failed to set socket option: {err}"),
+                    );
                 }
             }
 
-            // Set default options
-            if !config.contains_key(("server.listener", id, "socket.reuse-addr")) {
-                let _ = socket.set_reuseaddr(true);
+            // Traffic class markings are best effort as not all platforms support them
+            if let Some(tos) = settings.tos {
+                if let Err(err) = set_traffic_class(&socket, addr, tos) {
+                    config.new_build_warning(
+                        ("server.listener", id, "socket.tos"),
+                        format!("Failed to set traffic class on {addr}: {err}"),
+                    );
+                }
             }
 
-            listeners.push(Listener {
+            let listener = Listener {
                 socket,
                 addr,
-                ttl: config
-                    .property_or_else::<Option<u32>>(
-                        ("server.listener", id, "socket.ttl"),
-                        "server.socket.ttl",
-                        "false",
-                    )
-                    .unwrap_or_default(),
-                backlog: config
-                    .property_or_else::<Option<u32>>(
-                        ("server.listener", id, "socket.backlog"),
-                        "server.socket.backlog",
-                        "1024",
-                    )
-                    .unwrap_or_default(),
-                linger: config
-                    .property_or_else::<Option<Duration>>(
-                        ("server.listener", id, "socket.linger"),
-                        "server.socket.linger",
-                        "false",
-                    )
-                    .unwrap_or_default(),
-                nodelay: config
-                    .property_or_else(
-                        ("server.listener", id, "socket.nodelay"),
-                        "server.socket.nodelay",
-                        "true",
-                    )
-                    .unwrap_or(true),
-            });
+                ttl: settings.ttl,
+                backlog: settings.backlog,
+                linger: settings.linger,
+                nodelay: settings.nodelay,
+                reuse_port: settings.reuse_port,
+                keepalive: settings.keepalive,
+                ipv6_only: if addr.is_ipv6() {
+                    settings.ipv6_only
+                } else {
+                    None
+                },
+                proxy_protocol: settings.proxy_protocol,
+                accept_rate: settings.accept_rate.clone(),
+                read_timeout: settings.read_timeout,
+                write_timeout: settings.write_timeout,
+            };
+            if let Some(ipv6_only) = listener.ipv6_only {
+                if let Err(err) = SockRef::from(&listener.socket).set_only_v6(ipv6_only) {
+                    config.new_build_error(
+                        ("server.listener", id, "socket.ipv6-only"),
+                        format!("Failed to set socket option: {err}"),
+                    );
+                }
+            }
+            if listener.reuse_port {
+                #[cfg(not(target_env = "msvc"))]
+                if let Err(err) = listener.socket.set_reuseport(true) {
+                    config.new_build_error(
+                        ("server.listener", id, "socket.reuse-port"),
+                        format!("Failed to set socket option: {err}"),
+                    );
+                }
+                #[cfg(target_env = "msvc")]
+                config.new_build_warning(
+                    ("server.listener", id, "socket.reuse-port"),
+                    "SO_REUSEPORT is not supported on this platform",
+                );
+            }
+            if let Err(err) = listener.set_keepalive() {
+                config.new_build_error(
+                    ("server.listener", id, "socket.keepalive"),
+                    format!("Failed to set socket option: {err}"),
+                );
+            }
+            if let Err(err) = listener.set_tcp_options() {
+                config.new_build_warning(
+                    ("server.listener", id),
+                    format!("Failed to set socket option: {err}"),
+                );
+            }
+            listeners.push(listener);
         }
 
-        if listeners.is_empty() {
+        if listeners.is_empty() && unix_listeners.is_empty() {
+            // Invalid bind addresses have already been reported
+            if config
+                .values(("server.listener", id, "bind"))
+                .next()
+                .is_none()
+            {
+                config.new_build_error(
+                    ("server.listener", id),
+                    "No 'bind' directive found for listener",
+                );
+            }
+            return;
+        }
+
+        // Parse proxy networks and access lists
+        let proxy_networks = parse_networks(config, id, "proxy.trusted-networks");
+        if settings.proxy_protocol && proxy_networks.is_empty() {
+            // Any client could forge its address in a PROXY header otherwise
             config.new_build_error(
-                ("server.listener", id),
-                "No 'bind' directive found for listener",
+                ("server.listener", id, "proxy.trusted-networks"),
+                "The PROXY protocol is enabled but no trusted networks are configured",
             );
             return;
         }
-
-        // Parse proxy networks
-        let mut proxy_networks = Vec::new();
-        let proxy_keys = if config.has_prefix(("server.listener", id, "proxy.trusted-networks")) {
-            ("server.listener", id, "proxy.trusted-networks").as_key()
-        } else {
-            "server.proxy.trusted-networks".as_key()
-        };
-        for (_, network) in config.properties(proxy_keys) {
-            proxy_networks.push(network);
+        let allowed_networks = parse_networks(config, id, "allow");
+        let denied_networks = parse_denied_networks(config, id);
+        if !unix_listeners.is_empty()
+            && (!allowed_networks.is_empty() || !denied_networks.is_empty())
+        {
+            config.new_build_warning(
+                ("server.listener", id, "bind"),
+                "Access lists do not apply to Unix domain socket connections",
+            );
         }
 
         self.servers.push(Server {
@@ -206,10 +243,19 @@ impl Servers {
                     "8192",
                 )
                 .unwrap_or(8192),
+            max_connections_per_ip: config
+                .property::<u64>(("server.listener", id, "max-connections-per-ip"))
+                .or_else(|| config.property::<u64>("server.max-connections-per-ip")),
+            max_request_size: config
+                .property::<u64>(("server.listener", id, "max-request-size"))
+                .or_else(|| config.property::<u64>("server.max-request-size")),
             id: id_,
             protocol,
             listeners,
+            unix_listeners,
             proxy_networks,
+            allowed_networks,
+            denied_networks,
         });
     }
 
@@ -222,6 +268,10 @@ impl Servers {
             .collect::<Vec<_>>()
         {
             let id = id_.as_str();
+            if !is_enabled(config, id) {
+                continue;
+            }
+
             // Build TLS config
             let acceptor = if config
                 .property_or_default(("server.listener", id, "tls.enable"), "true")
@@ -251,6 +301,29 @@ impl Servers {
                     );
                 }
 
+                // Parse minimum protocol version
+                let min_version_key =
+                    if config.contains_key(("server.listener", id, "tls.min-version")) {
+                        ("server.listener", id, "tls.min-version").as_key()
+                    } else {
+                        "server.tls.min-version".as_key()
+                    };
+                match config.value(min_version_key.as_str()) {
+                    Some("TLSv1.2" | "0x0303") | None => (),
+                    Some("TLSv1.3" | "0x0304") => tls_v2 = false,
+                    Some(version) => {
+                        let err = format!("Unsupported minimum TLS version {version:?}");
+                        config.new_parse_error(min_version_key.as_str(), err);
+                    }
+                }
+                if !tls_v2 && !tls_v3 {
+                    config.new_build_error(
+                        ("server.listener", id, "tls"),
+                        "All TLS protocol versions are disabled",
+                    );
+                    return;
+                }
+
                 // Parse cipher suites
                 let mut disabled_ciphers: Vec<SupportedCipherSuite> = Vec::new();
                 let cipher_keys =
@@ -263,9 +336,26 @@ impl Servers {
                     disabled_ciphers.push(protocol);
                 }
 
+                // Parse allowed cipher suites, in order of preference
+                let mut enabled_ciphers: Vec<SupportedCipherSuite> = Vec::new();
+                let cipher_keys = if config.has_prefix(("server.listener", id, "tls.cipher-suites"))
+                {
+                    ("server.listener", id, "tls.cipher-suites").as_key()
+                } else {
+                    "server.tls.cipher-suites".as_key()
+                };
+                for (_, cipher) in config.properties::<SupportedCipherSuite>(cipher_keys) {
+                    enabled_ciphers.push(cipher);
+                }
+
                 // Build cert provider
                 let mut provider = default_provider();
-                if !disabled_ciphers.is_empty() {
+                if !enabled_ciphers.is_empty() {
+                    provider.cipher_suites = enabled_ciphers
+                        .into_iter()
+                        .filter(|suite| !disabled_ciphers.contains(suite))
+                        .collect();
+                } else if !disabled_ciphers.is_empty() {
                     provider.cipher_suites = ALL_CIPHER_SUITES
                         .iter()
                         .filter(|suite| !disabled_ciphers.contains(suite))
@@ -273,6 +363,52 @@ impl Servers {
                         .collect();
                 }
 
+                // Certificates selected by SNI on this listener only
+                let sni = parse_sni_certificates(config, id);
+                let ocsp = parse_ocsp_stapler(config, id);
+                let implicit = config
+                    .property_or_default(("server.listener", id, "tls.implicit"), "false")
+                    .unwrap_or(false);
+                if implicit && sni.is_empty() {
+                    let core = core.load();
+                    if core.tls.certificates.load().is_empty() && core.tls.acme_providers.is_empty()
+                    {
+                        if core.tls.self_signed_cert.is_some() {
+                            config.new_build_warning(
+                                ("server.listener", id, "tls.implicit"),
+                                format!(
+                                    "Listener {id:?} uses implicit TLS but no certificates are configured, a self-signed certificate will be used"
+                                ),
+                            );
+                        } else {
+                            config.new_build_error(
+                                ("server.listener", id, "tls.implicit"),
+                                format!(
+                                    "Listener {id:?} uses implicit TLS but no certificates are configured"
+                                ),
+                            );
+
+                            // Never fall back to plain text
+                            self.servers.retain(|server| server.id != id);
+                            continue;
+                        }
+                    }
+                }
+                let resolver = if !sni.is_empty() || ocsp.is_some() {
+                    let resolver = Arc::new(CertificateResolver {
+                        core: core.clone(),
+                        sni,
+                        ocsp: ocsp.clone(),
+                    });
+                    if let Some(ocsp) = ocsp {
+                        // Refresh tasks are started once the listeners are spawned
+                        self.ocsp_staplers.push((ocsp, resolver.clone()));
+                    }
+                    resolver
+                } else {
+                    resolver.clone()
+                };
+
                 // Build server config
                 let mut server_config = match ServerConfig::builder_with_provider(provider.into())
                     .with_protocol_versions(if tls_v3 == tls_v2 {
@@ -284,7 +420,7 @@ impl Servers {
                     }) {
                     Ok(server_config) => server_config
                         .with_no_client_auth()
-                        .with_cert_resolver(resolver.clone()),
+                        .with_cert_resolver(resolver),
                     Err(err) => {
                         config.new_build_error(
                             ("server.listener", id, "tls"),
@@ -307,9 +443,7 @@ impl Servers {
                 TcpAcceptor::Tls {
                     acceptor: TlsAcceptor::from(default_config.clone()),
                     config: default_config,
-                    implicit: config
-                        .property_or_default(("server.listener", id, "tls.implicit"), "false")
-                        .unwrap_or(false),
+                    implicit,
                 }
             } else {
                 TcpAcceptor::Plain
@@ -320,20 +454,579 @@ impl Servers {
     }
 }
 
+impl ListenerSettings {
+    // Reads and validates all the settings of a listener, returning every
+    // error found rather than stopping at the first one
+    pub fn from_config(
+        config: &mut Config,
+        id: &str,
+    ) -> Result<ListenerSettings, Vec<(String, ConfigError)>> {
+        // Parse with an empty error map so that errors raised for keys that
+        // already had one are reported as well, then restore the previous ones
+        let previous_errors = std::mem::take(&mut config.errors);
+        let settings = ListenerSettings::parse(config, id);
+        let new_errors = std::mem::replace(&mut config.errors, previous_errors);
+        let mut errors = new_errors
+            .iter()
+            .map(|(key, error)| (key.clone(), error.clone()))
+            .collect::<Vec<_>>();
+        config.errors.extend(new_errors);
+
+        if errors.is_empty() {
+            Ok(settings)
+        } else {
+            errors.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+            Err(errors)
+        }
+    }
+
+    // Invalid values are recorded as config errors and replaced by their defaults
+    pub(crate) fn parse(config: &mut Config, id: &str) -> ListenerSettings {
+        let protocol = config
+            .value(("server.listener", id, "protocol"))
+            .and_then(|protocol| protocol.parse::<ServerProtocol>().ok())
+            .unwrap_or_default();
+        let implicit_tls = config
+            .property_or_default(("server.listener", id, "tls.implicit"), "false")
+            .unwrap_or(false);
+        let mut bind = Vec::new();
+        let mut unix_bind = Vec::new();
+        for (key, value) in config
+            .values(("server.listener", id, "bind"))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<Vec<_>>()
+        {
+            // Unix domain sockets are bound using the "unix:/path/to.sock" form
+            if let Some(path) = value.strip_prefix("unix:") {
+                let path = PathBuf::from(path);
+                if path.as_os_str().is_empty() {
+                    config.new_parse_error(key, "Missing path for Unix domain socket");
+                } else if unix_bind.contains(&path) {
+                    config.new_parse_error(
+                        key,
+                        format!("Socket {} is bound more than once", path.display()),
+                    );
+                } else {
+                    unix_bind.push(path);
+                }
+            } else {
+                let addr = if let Some(spec) = value.strip_prefix("%if:") {
+                    parse_interface_addr(config, &key, spec, protocol, implicit_tls)
+                } else {
+                    parse_bind_addr(config, &key, &value, protocol, implicit_tls)
+                };
+
+                // Every address in the list shares the same socket options
+                if let Some(addr) = addr {
+                    if bind.contains(&addr) {
+                        config.new_parse_error(
+                            key,
+                            format!("Address {addr} is bound more than once"),
+                        );
+                    } else {
+                        bind.push(addr);
+                    }
+                }
+            }
+        }
+
+        ListenerSettings {
+            bind,
+            unix_bind,
+            backlog: parse_backlog(config, id),
+            ttl: config
+                .property_or_else::<Option<u32>>(
+                    ("server.listener", id, "socket.ttl"),
+                    "server.socket.ttl",
+                    "false",
+                )
+                .unwrap_or_default(),
+            linger: config
+                .property_or_else::<Option<Duration>>(
+                    ("server.listener", id, "socket.linger"),
+                    "server.socket.linger",
+                    "false",
+                )
+                .unwrap_or_default(),
+            nodelay: config
+                .property_or_else(
+                    ("server.listener", id, "socket.nodelay"),
+                    "server.socket.nodelay",
+                    "true",
+                )
+                .unwrap_or(true),
+            reuse_addr: config
+                .property_or_else(
+                    ("server.listener", id, "socket.reuse-addr"),
+                    "server.socket.reuse-addr",
+                    "true",
+                )
+                .unwrap_or(true),
+            reuse_port: config
+                .property_or_else(
+                    ("server.listener", id, "socket.reuse-port"),
+                    "server.socket.reuse-port",
+                    "false",
+                )
+                .unwrap_or(false),
+            keepalive: config
+                .property_or_else::<Option<Duration>>(
+                    ("server.listener", id, "socket.keepalive"),
+                    "server.socket.keepalive",
+                    // Unless configured, keepalive depends on the protocol
+                    protocol.default_keepalive(),
+                )
+                .unwrap_or_default(),
+            ipv6_only: parse_socket_option(config, id, "ipv6-only"),
+            send_buffer_size: parse_socket_option(config, id, "send-buffer-size"),
+            recv_buffer_size: parse_socket_option(config, id, "recv-buffer-size"),
+            tos: parse_tos(config, id),
+            permissions: config
+                .value_or_else(
+                    ("server.listener", id, "socket.permissions"),
+                    "server.socket.permissions",
+                )
+                .map(|value| value.to_string())
+                .and_then(
+                    |value| match u32::from_str_radix(value.trim_start_matches("0o"), 8) {
+                        Ok(mode) if mode <= 0o7777 => Some(mode),
+                        _ => {
+                            config.new_parse_error(
+                                ("server.listener", id, "socket.permissions"),
+                                format!("Invalid file mode {value:?}, expected an octal number"),
+                            );
+                            None
+                        }
+                    },
+                ),
+            proxy_protocol: config
+                .property_or_else(
+                    ("server.listener", id, "proxy.enable"),
+                    "server.proxy.enable",
+                    "false",
+                )
+                .unwrap_or(false),
+            accept_rate: config
+                .property_or_else::<Option<Rate>>(
+                    ("server.listener", id, "accept-rate"),
+                    "server.accept-rate",
+                    "false",
+                )
+                .unwrap_or_default(),
+            read_timeout: config
+                .property_or_else::<Option<Duration>>(
+                    ("server.listener", id, "read-timeout"),
+                    "server.read-timeout",
+                    "false",
+                )
+                .unwrap_or_default(),
+            write_timeout: config
+                .property_or_else::<Option<Duration>>(
+                    ("server.listener", id, "write-timeout"),
+                    "server.write-timeout",
+                    "false",
+                )
+                .unwrap_or_default(),
+        }
+    }
+}
+
+// Addresses without a port are bound to the standard port of the protocol
+fn parse_bind_addr(
+    config: &mut Config,
+    key: &str,
+    value: &str,
+    protocol: ServerProtocol,
+    implicit_tls: bool,
+) -> Option<SocketAddr> {
+    if let Ok(addr) = value.parse::<SocketAddr>() {
+        return Some(addr);
+    }
+
+    let ip = value
+        .strip_prefix('[')
+        .and_then(|ip| ip.strip_suffix(']'))
+        .unwrap_or(value);
+    match ip.parse::<IpAddr>() {
+        Ok(ip) => bind_port(config, key, None, protocol, implicit_tls)
+            .map(|port| SocketAddr::new(ip, port)),
+        Err(_) => config.try_parse_value::<SocketAddr>(key, value),
+    }
+}
+
+fn bind_port(
+    config: &mut Config,
+    key: &str,
+    port: Option<u16>,
+    protocol: ServerProtocol,
+    implicit_tls: bool,
+) -> Option<u16> {
+    let port = port.or_else(|| protocol.default_port(implicit_tls));
+    if port.is_none() {
+        config.new_parse_error(
+            key,
+            format!(
+                "Missing port, {protocol} listeners{} have no standard port",
+                if implicit_tls {
+                    " with implicit TLS"
+                } else {
+                    ""
+                }
+            ),
+        );
+    }
+    port
+}
+
+// Interface addresses are bound using the "%if:<name>[:v4|:v6]%[:<port>]" form, which
+// resolves to the first usable address of that family on the interface, IPv4 by default
+fn parse_interface_addr(
+    config: &mut Config,
+    key: &str,
+    spec: &str,
+    protocol: ServerProtocol,
+    implicit_tls: bool,
+) -> Option<SocketAddr> {
+    let (name, is_v6, port) = match spec.split_once('%').and_then(|(name, port)| {
+        let port = if !port.is_empty() {
+            Some(port.strip_prefix(':')?.parse::<u16>().ok()?)
+        } else {
+            None
+        };
+        match name.rsplit_once(':') {
+            Some((name, "v4")) => Some((name, false, port)),
+            Some((name, "v6")) => Some((name, true, port)),
+            Some(_) => None,
+            None => Some((name, false, port)),
+        }
+    }) {
+        Some((name, is_v6, port)) if !name.is_empty() => (name, is_v6, port),
+        _ => {
+            config.new_parse_error(
+                key,
+                "Invalid interface address, expected %if:<name>[:v4|:v6]%[:<port>]",
+            );
+            return None;
+        }
+    };
+    let port = bind_port(config, key, port, protocol, implicit_tls)?;
+
+    let interfaces = match if_addrs::get_if_addrs() {
+        Ok(interfaces) => interfaces,
+        Err(err) => {
+            config.new_build_error(key, format!("Failed to list network interfaces: {err}"));
+            return None;
+        }
+    };
+    let addrs = interfaces
+        .iter()
+        .filter(|interface| interface.name == name)
+        .map(|interface| interface.ip())
+        .collect::<Vec<_>>();
+    if addrs.is_empty() {
+        config.new_build_error(key, format!("Network interface {name:?} not found"));
+        return None;
+    }
+
+    match select_interface_addr(&addrs, is_v6) {
+        Some(ip) => Some(SocketAddr::new(ip, port)),
+        None => {
+            config.new_build_error(
+                key,
+                format!(
+                    "Network interface {name:?} has no {} address",
+                    if is_v6 { "IPv6" } else { "IPv4" }
+                ),
+            );
+            None
+        }
+    }
+}
+
+// Link-local addresses are only used when the interface has no other address of
+// the requested family, as they are not reachable from other networks
+fn select_interface_addr(addrs: &[IpAddr], is_v6: bool) -> Option<IpAddr> {
+    let is_link_local = |ip: &IpAddr| match ip {
+        IpAddr::V4(ip) => ip.is_link_local(),
+        IpAddr::V6(ip) => (ip.segments()[0] & 0xffc0) == 0xfe80,
+    };
+    let mut addrs = addrs.iter().filter(|ip| ip.is_ipv6() == is_v6);
+    addrs
+        .clone()
+        .find(|ip| !is_link_local(ip))
+        .or_else(|| addrs.next())
+        .copied()
+}
+
+// Network lists set on a listener replace the server-wide ones entirely
+// (except for denied networks, see parse_denied_networks)
+fn parse_networks(config: &mut Config, id: &str, option: &str) -> Vec<IpAddrMask> {
+    let keys = if config.has_prefix(("server.listener", id, option)) {
+        ("server.listener", id, option).as_key()
+    } else {
+        ("server", option).as_key()
+    };
+    config
+        .properties(keys)
+        .into_iter()
+        .map(|(_, network)| network)
+        .collect()
+}
+
+// Denied networks are never overridden, a listener can only add to the server-wide ones
+fn parse_denied_networks(config: &mut Config, id: &str) -> Vec<IpAddrMask> {
+    let mut networks: Vec<IpAddrMask> = config
+        .properties(("server", "deny").as_key())
+        .into_iter()
+        .map(|(_, network)| network)
+        .collect();
+    networks.extend(
+        config
+            .properties::<IpAddrMask>(("server.listener", id, "deny").as_key())
+            .into_iter()
+            .map(|(_, network)| network),
+    );
+    networks
+}
+
+// Disabled listeners are skipped entirely, the flag is read after env macros and
+// profiles are applied so it can be set per node
+fn is_enabled(config: &mut Config, id: &str) -> bool {
+    config
+        .property_or_default(("server.listener", id, "enable"), "true")
+        .unwrap_or(true)
+}
+
+// The TOS byte can be set directly or as a DSCP code point, which occupies its
+// upper six bits. When both are present the DSCP value is used.
+fn parse_tos(config: &mut Config, id: &str) -> Option<u32> {
+    if let Some(dscp) = parse_socket_option::<u32>(config, id, "dscp") {
+        if dscp <= 63 {
+            Some(dscp << 2)
+        } else {
+            config.new_parse_error(
+                ("server.listener", id, "socket.dscp"),
+                format!("Invalid DSCP value {dscp}, expected a number between 0 and 63"),
+            );
+            None
+        }
+    } else {
+        match parse_socket_option::<u32>(config, id, "tos") {
+            Some(tos) if tos > 255 => {
+                config.new_parse_error(
+                    ("server.listener", id, "socket.tos"),
+                    format!("Invalid TOS value {tos}, expected a number between 0 and 255"),
+                );
+                None
+            }
+            tos => tos,
+        }
+    }
+}
+
+// IPv4 sockets use IP_TOS while IPv6 sockets use IPV6_TCLASS
+fn set_traffic_class(socket: &TcpSocket, addr: SocketAddr, tos: u32) -> std::io::Result<()> {
+    let socket = SockRef::from(socket);
+    if addr.is_ipv4() {
+        socket.set_tos(tos)
+    } else {
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "macos",
+            target_os = "ios"
+        ))]
+        {
+            socket.set_tclass_v6(tos)
+        }
+
+        #[cfg(not(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "macos",
+            target_os = "ios"
+        )))]
+        {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "IPV6_TCLASS is not supported on this platform",
+            ))
+        }
+    }
+}
+
+fn parse_socket_option<T: ParseValue>(config: &mut Config, id: &str, option: &str) -> Option<T> {
+    let value = config
+        .value_or_else(
+            ("server.listener", id, "socket", option),
+            ("server.socket", option),
+        )?
+        .to_string();
+    config.try_parse_value(("server.listener", id, "socket", option), &value)
+}
+
+fn parse_ocsp_stapler(config: &mut Config, id: &str) -> Option<Arc<OcspStapler>> {
+    if !config
+        .property_or_else(
+            ("server.listener", id, "tls.ocsp.enable"),
+            "server.tls.ocsp.enable",
+            "false",
+        )
+        .unwrap_or(false)
+    {
+        return None;
+    }
+
+    Some(Arc::new(OcspStapler::new(
+        config
+            .value(("server.listener", id, "tls.ocsp.path"))
+            .map(PathBuf::from),
+        config
+            .property_or_else(
+                ("server.listener", id, "tls.ocsp.refresh"),
+                "server.tls.ocsp.refresh",
+                "1h",
+            )
+            .unwrap_or(Duration::from_secs(3600)),
+        config
+            .property_or_else(
+                ("server.listener", id, "tls.ocsp.timeout"),
+                "server.tls.ocsp.timeout",
+                "10s",
+            )
+            .unwrap_or(Duration::from_secs(10)),
+    )))
+}
+
+const DEFAULT_BACKLOG: u32 = 1024;
+
+fn parse_backlog(config: &mut Config, id: &str) -> Option<u32> {
+    let key = if config.contains_key(("server.listener", id, "socket.backlog")) {
+        ("server.listener", id, "socket.backlog").as_key()
+    } else {
+        "server.socket.backlog".as_key()
+    };
+    let max_backlog = max_backlog();
+
+    match config.property::<u32>(key.as_str()) {
+        Some(0) => {
+            config.new_build_error(key.as_str(), "Backlog must be greater than zero");
+            None
+        }
+        Some(backlog) if backlog > max_backlog => {
+            config.new_build_warning(
+                key.as_str(),
+                format!(
+                    "Backlog {backlog} exceeds the platform maximum of {max_backlog}, using {max_backlog}"
+                ),
+            );
+            Some(max_backlog)
+        }
+        Some(backlog) => Some(backlog),
+        None => Some(DEFAULT_BACKLOG.min(max_backlog)),
+    }
+}
+
+fn max_backlog() -> u32 {
+    // Linux exposes the current SOMAXCONN limit through procfs
+    #[cfg(target_os = "linux")]
+    if let Some(max) = std::fs::read_to_string("/proc/sys/net/core/somaxconn")
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+    {
+        return max;
+    }
+
+    // The backlog is passed to listen() as a signed integer
+    i32::MAX as u32
+}
+
+fn build_unix_listener(
+    config: &mut Config,
+    id: &str,
+    path: &Path,
+    settings: &ListenerSettings,
+) -> Option<UnixSocketListener> {
+    #[cfg(unix)]
+    {
+        let socket = socket2::Socket::new(socket2::Domain::UNIX, socket2::Type::STREAM, None);
+        let socket = match socket {
+            Ok(socket) => socket,
+            Err(err) => {
+                config.new_build_error(
+                    ("server.listener", id, "bind"),
+                    format!("Failed to create socket: {err}"),
+                );
+                return None;
+            }
+        };
+
+        Some(UnixSocketListener {
+            socket,
+            path: path.into(),
+            backlog: settings.backlog,
+            permissions: settings.permissions,
+        })
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = (path, settings);
+        config.new_build_error(
+            ("server.listener", id, "bind"),
+            "Unix domain sockets are not supported on this platform",
+        );
+        None
+    }
+}
+
 impl ParseValue for ServerProtocol {
     fn parse_value(value: &str) -> utils::config::Result<Self> {
-        if value.eq_ignore_ascii_case("smtp") {
-            Ok(Self::Smtp)
-        } else if value.eq_ignore_ascii_case("lmtp") {
-            Ok(Self::Lmtp)
-        } else if value.eq_ignore_ascii_case("imap") {
-            Ok(Self::Imap)
-        } else if value.eq_ignore_ascii_case("http") | value.eq_ignore_ascii_case("https") {
-            Ok(Self::Http)
-        } else if value.eq_ignore_ascii_case("managesieve") {
-            Ok(Self::ManageSieve)
-        } else {
-            Err(format!("Invalid server protocol type {:?}.", value,))
+        value.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::select_interface_addr;
+
+    #[test]
+    fn interface_addr_skips_link_local() {
+        let addrs = |list: &[&str]| {
+            list.iter()
+                .map(|ip| ip.parse::<IpAddr>().unwrap())
+                .collect::<Vec<_>>()
+        };
+        for (list, is_v6, expected) in [
+            (
+                &["169.254.10.1", "fe80::1", "192.0.2.1", "2001:db8::1"][..],
+                false,
+                Some("192.0.2.1"),
+            ),
+            (
+                &["169.254.10.1", "fe80::1", "192.0.2.1", "2001:db8::1"][..],
+                true,
+                Some("2001:db8::1"),
+            ),
+            (&["febf::1", "2001:db8::1"][..], true, Some("2001:db8::1")),
+            (&["fec0::1", "fe80::2"][..], true, Some("fec0::1")),
+            (
+                &["169.254.10.1", "fe80::1"][..],
+                false,
+                Some("169.254.10.1"),
+            ),
+            (&["169.254.10.1", "fe80::1"][..], true, Some("fe80::1")),
+            (&["192.0.2.1"][..], true, None),
+        ] {
+            assert_eq!(
+                select_interface_addr(&addrs(list), is_v6),
+                expected.map(|ip| ip.parse::<IpAddr>().unwrap()),
+                "{list:?} (v6: {is_v6})"
+            );
         }
     }
 }
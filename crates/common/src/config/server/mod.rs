@@ -1,18 +1,39 @@
-use std::{fmt::Display, net::SocketAddr, time::Duration};
+use std::{
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
 use ahash::AHashMap;
-use tokio::net::TcpSocket;
-use utils::config::ipmask::IpAddrMask;
+use tokio::{net::TcpSocket, sync::watch};
+use utils::config::{ipmask::IpAddrMask, Rate};
 
-use crate::listener::TcpAcceptor;
+use crate::listener::{ocsp::OcspStapler, tls::CertificateResolver, TcpAcceptor};
 
 pub mod listener;
 pub mod tls;
 
-#[derive(Default)]
+pub const UNIX_PEER_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+
 pub struct Servers {
     pub servers: Vec<Server>,
     pub tcp_acceptors: AHashMap<String, TcpAcceptor>,
+    pub ocsp_staplers: Vec<(Arc<OcspStapler>, Arc<CertificateResolver>)>,
+    pub(crate) shutdown_tx: watch::Sender<bool>,
+}
+
+impl Default for Servers {
+    fn default() -> Self {
+        Self {
+            servers: Vec::new(),
+            tcp_acceptors: AHashMap::new(),
+            ocsp_staplers: Vec::new(),
+            shutdown_tx: watch::channel(false).0,
+        }
+    }
 }
 
 #[derive(Debug, Default)]
@@ -20,8 +41,13 @@ pub struct Server {
     pub id: String,
     pub protocol: ServerProtocol,
     pub listeners: Vec<Listener>,
+    pub unix_listeners: Vec<UnixSocketListener>,
     pub proxy_networks: Vec<IpAddrMask>,
+    pub allowed_networks: Vec<IpAddrMask>,
+    pub denied_networks: Vec<IpAddrMask>,
     pub max_connections: u64,
+    pub max_connections_per_ip: Option<u64>,
+    pub max_request_size: Option<u64>,
 }
 
 #[derive(Debug)]
@@ -34,6 +60,67 @@ pub struct Listener {
     pub ttl: Option<u32>,
     pub linger: Option<Duration>,
     pub nodelay: bool,
+    pub reuse_port: bool,
+    pub keepalive: Option<Duration>,
+
+    // Sets IPV6_V6ONLY on IPv6 sockets, ignored for IPv4. Disabling it allows
+    // a single "[::]" listener to also accept IPv4-mapped connections instead
+    // of requiring separate IPv4 and IPv6 binds on the same port.
+    pub ipv6_only: Option<bool>,
+
+    // Require a PROXY protocol header on every connection
+    pub proxy_protocol: bool,
+
+    // Maximum rate of new connections, connections above it are closed right away
+    pub accept_rate: Option<Rate>,
+
+    // Accepted connections are closed when a read or write makes no progress
+    // for this long, regardless of TCP keepalive
+    pub read_timeout: Option<Duration>,
+    pub write_timeout: Option<Duration>,
+}
+
+// Unix domain socket peers have no network address. Sessions and expressions see
+// UNIX_PEER_ADDR as both the local and remote address, and the IP access lists,
+// blocked IPs and per-IP connection limits are not applied to them.
+#[derive(Debug)]
+pub struct UnixSocketListener {
+    pub socket: socket2::Socket,
+    pub path: PathBuf,
+    pub backlog: Option<u32>,
+
+    // File mode applied to the socket file after binding
+    pub permissions: Option<u32>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct ListenerOptions {
+    pub ttl: u32,
+    pub linger: Option<Duration>,
+    pub nodelay: bool,
+}
+
+// Validated settings of a listener, read before any socket is created
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ListenerSettings {
+    pub bind: Vec<SocketAddr>,
+    pub unix_bind: Vec<PathBuf>,
+    pub backlog: Option<u32>,
+    pub ttl: Option<u32>,
+    pub linger: Option<Duration>,
+    pub nodelay: bool,
+    pub reuse_addr: bool,
+    pub reuse_port: bool,
+    pub keepalive: Option<Duration>,
+    pub ipv6_only: Option<bool>,
+    pub send_buffer_size: Option<u32>,
+    pub recv_buffer_size: Option<u32>,
+    pub tos: Option<u32>,
+    pub permissions: Option<u32>,
+    pub proxy_protocol: bool,
+    pub accept_rate: Option<Rate>,
+    pub read_timeout: Option<Duration>,
+    pub write_timeout: Option<Duration>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
@@ -44,9 +131,31 @@ pub enum ServerProtocol {
     Imap,
     Http,
     ManageSieve,
+    Pop3,
+}
+
+impl Servers {
+    pub fn by_protocol(&self, protocol: ServerProtocol) -> impl Iterator<Item = &Server> {
+        self.servers
+            .iter()
+            .filter(move |server| server.protocol == protocol)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Server> {
+        self.servers.iter().find(|server| server.id == id)
+    }
 }
 
 impl ServerProtocol {
+    pub const ALL: [ServerProtocol; 6] = [
+        ServerProtocol::Smtp,
+        ServerProtocol::Lmtp,
+        ServerProtocol::Imap,
+        ServerProtocol::Http,
+        ServerProtocol::ManageSieve,
+        ServerProtocol::Pop3,
+    ];
+
     pub fn as_str(&self) -> &'static str {
         match self {
             ServerProtocol::Smtp => "smtp",
@@ -54,6 +163,47 @@ impl ServerProtocol {
             ServerProtocol::Imap => "imap",
             ServerProtocol::Http => "http",
             ServerProtocol::ManageSieve => "managesieve",
+            ServerProtocol::Pop3 => "pop3",
+        }
+    }
+
+    // Keepalive for listeners that do not set one, enabled where clients idle on open connections
+    pub fn default_keepalive(&self) -> &'static str {
+        match self {
+            ServerProtocol::Imap | ServerProtocol::ManageSieve | ServerProtocol::Http => "5m",
+            ServerProtocol::Smtp | ServerProtocol::Lmtp | ServerProtocol::Pop3 => "false",
+        }
+    }
+
+    // Standard port for listeners that omit it in their bind address. HTTP is
+    // served on both 80 and 443, and LMTP and ManageSieve have no implicit TLS
+    // port, so those listeners need an explicit one.
+    pub fn default_port(&self, implicit_tls: bool) -> Option<u16> {
+        match (self, implicit_tls) {
+            (ServerProtocol::Smtp, false) => Some(25),
+            (ServerProtocol::Smtp, true) => Some(465),
+            (ServerProtocol::Lmtp, false) => Some(24),
+            (ServerProtocol::Imap, false) => Some(143),
+            (ServerProtocol::Imap, true) => Some(993),
+            (ServerProtocol::ManageSieve, false) => Some(4190),
+            (ServerProtocol::Pop3, false) => Some(110),
+            (ServerProtocol::Pop3, true) => Some(995),
+            (ServerProtocol::Lmtp | ServerProtocol::ManageSieve, true)
+            | (ServerProtocol::Http, _) => None,
+        }
+    }
+
+    pub fn too_many_connections(&self) -> &'static [u8] {
+        match self {
+            ServerProtocol::Smtp | ServerProtocol::Lmtp => {
+                b"421 4.3.2 Too many concurrent connections, please try again later.\r\n"
+            }
+            ServerProtocol::Imap => b"* BYE Too many concurrent connections.\r\n",
+            ServerProtocol::Http => {
+                b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            }
+            ServerProtocol::ManageSieve => b"BYE \"Too many concurrent connections.\"\r\n",
+            ServerProtocol::Pop3 => b"-ERR Too many concurrent connections.\r\n",
         }
     }
 }
@@ -63,3 +213,69 @@ impl Display for ServerProtocol {
         f.write_str(self.as_str())
     }
 }
+
+// Accepts the names emitted by Display in any case, plus "https" as an alias of "http"
+impl FromStr for ServerProtocol {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.eq_ignore_ascii_case("https") {
+            return Ok(ServerProtocol::Http);
+        }
+
+        ServerProtocol::ALL
+            .into_iter()
+            .find(|protocol| value.eq_ignore_ascii_case(protocol.as_str()))
+            .ok_or_else(|| {
+                format!(
+                    "Invalid server protocol type {value:?}, expected one of {}.",
+                    ServerProtocol::ALL
+                        .iter()
+                        .map(|protocol| protocol.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::config::utils::ParseValue;
+
+    use super::ServerProtocol;
+
+    #[test]
+    fn parse_server_protocol() {
+        for protocol in ServerProtocol::ALL {
+            assert_eq!(protocol.to_string().parse::<ServerProtocol>(), Ok(protocol));
+            assert_eq!(
+                ServerProtocol::parse_value(&protocol.to_string().to_uppercase()),
+                Ok(protocol)
+            );
+        }
+        assert_eq!("HTTPS".parse::<ServerProtocol>(), Ok(ServerProtocol::Http));
+
+        let err = "gopher".parse::<ServerProtocol>().unwrap_err();
+        assert!(err.contains("\"gopher\""), "{err}");
+        assert!(
+            err.contains("smtp, lmtp, imap, http, managesieve, pop3"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn default_port() {
+        for (protocol, plain, implicit_tls) in [
+            (ServerProtocol::Smtp, Some(25), Some(465)),
+            (ServerProtocol::Lmtp, Some(24), None),
+            (ServerProtocol::Imap, Some(143), Some(993)),
+            (ServerProtocol::Http, None, None),
+            (ServerProtocol::ManageSieve, Some(4190), None),
+            (ServerProtocol::Pop3, Some(110), Some(995)),
+        ] {
+            assert_eq!(protocol.default_port(false), plain, "{protocol}");
+            assert_eq!(protocol.default_port(true), implicit_tls, "{protocol}");
+        }
+    }
+}
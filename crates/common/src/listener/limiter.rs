@@ -22,6 +22,8 @@
 */
 
 use std::{
+    collections::hash_map::Entry,
+    net::IpAddr,
     sync::{
         atomic::{AtomicU64, Ordering},
         Arc,
@@ -29,6 +31,8 @@ use std::{
     time::SystemTime,
 };
 
+use ahash::AHashMap;
+use parking_lot::Mutex;
 use utils::config::Rate;
 
 #[derive(Debug)]
@@ -46,6 +50,19 @@ pub struct ConcurrencyLimiter {
 #[derive(Default)]
 pub struct InFlight {
     concurrent: Arc<AtomicU64>,
+    // Per-IP slot, released together with the global one
+    _ip: Option<IpInFlight>,
+}
+
+#[derive(Debug, Clone)]
+pub struct IpConcurrencyLimiter {
+    pub max_concurrent: u64,
+    pub concurrent: Arc<Mutex<AHashMap<IpAddr, u64>>>,
+}
+
+pub struct IpInFlight {
+    ip: IpAddr,
+    concurrent: Arc<Mutex<AHashMap<IpAddr, u64>>>,
 }
 
 impl Drop for InFlight {
@@ -54,6 +71,19 @@ impl Drop for InFlight {
     }
 }
 
+impl Drop for IpInFlight {
+    fn drop(&mut self) {
+        // Remove the entry once the last connection from this IP is gone
+        if let Entry::Occupied(mut entry) = self.concurrent.lock().entry(self.ip) {
+            if *entry.get() <= 1 {
+                entry.remove();
+            } else {
+                *entry.get_mut() -= 1;
+            }
+        }
+    }
+}
+
 impl RateLimiter {
     pub fn new(rate: &Rate) -> Self {
         RateLimiter {
@@ -109,6 +139,7 @@ impl ConcurrencyLimiter {
             self.concurrent.fetch_add(1, Ordering::Relaxed);
             Some(InFlight {
                 concurrent: self.concurrent.clone(),
+                _ip: None,
             })
         } else {
             None
@@ -122,12 +153,49 @@ impl ConcurrencyLimiter {
     pub fn is_active(&self) -> bool {
         self.concurrent.load(Ordering::Relaxed) > 0
     }
+
+    pub fn num_concurrent(&self) -> u64 {
+        self.concurrent.load(Ordering::Relaxed)
+    }
+}
+
+impl IpConcurrencyLimiter {
+    pub fn new(max_concurrent: u64) -> Self {
+        IpConcurrencyLimiter {
+            max_concurrent,
+            concurrent: Arc::new(Mutex::new(AHashMap::new())),
+        }
+    }
+
+    pub fn is_allowed(&self, ip: IpAddr) -> Option<IpInFlight> {
+        // Rejected IPs are not tracked, entries only exist while a connection is open
+        let mut concurrent = self.concurrent.lock();
+        let count = concurrent.get(&ip).copied().unwrap_or(0);
+        if count < self.max_concurrent {
+            concurrent.insert(ip, count + 1);
+            Some(IpInFlight {
+                ip,
+                concurrent: self.concurrent.clone(),
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn num_concurrent(&self, ip: &IpAddr) -> u64 {
+        self.concurrent.lock().get(ip).copied().unwrap_or(0)
+    }
 }
 
 impl InFlight {
     pub fn num_concurrent(&self) -> u64 {
         self.concurrent.load(Ordering::Relaxed)
     }
+
+    pub fn with_ip_limit(mut self, ip: IpInFlight) -> Self {
+        self._ip = Some(ip);
+        self
+    }
 }
 
 fn now() -> u64 {
@@ -136,3 +204,27 @@ fn now() -> u64 {
         .unwrap_or_default()
         .as_secs()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::IpConcurrencyLimiter;
+
+    #[test]
+    fn ip_limiter_releases_entries() {
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+
+        // Rejected connections leave no entry behind
+        let limiter = IpConcurrencyLimiter::new(0);
+        assert!(limiter.is_allowed(ip).is_none());
+        assert!(limiter.concurrent.lock().is_empty());
+
+        let limiter = IpConcurrencyLimiter::new(1);
+        let in_flight = limiter.is_allowed(ip).unwrap();
+        assert!(limiter.is_allowed(ip).is_none());
+        assert_eq!(limiter.num_concurrent(&ip), 1);
+        drop(in_flight);
+        assert!(limiter.concurrent.lock().is_empty());
+    }
+}
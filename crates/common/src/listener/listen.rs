@@ -24,13 +24,15 @@
 use std::{
     net::{IpAddr, SocketAddr},
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use arc_swap::ArcSwap;
 use proxy_header::io::ProxiedStream;
 use rustls::crypto::ring::cipher_suite::TLS13_AES_128_GCM_SHA256;
+use socket2::{SockRef, TcpKeepalive};
 use tokio::{
+    io::AsyncWriteExt,
     net::{TcpListener, TcpStream},
     sync::watch,
 };
@@ -39,13 +41,15 @@ use tracing::Span;
 use utils::{config::Config, UnwrapFailure};
 
 use crate::{
-    config::server::{Listener, Server, ServerProtocol, Servers},
+    config::server::{Listener, ListenerOptions, Server, ServerProtocol, Servers, UNIX_PEER_ADDR},
     Core,
 };
 
 use super::{
-    limiter::ConcurrencyLimiter, ServerInstance, SessionData, SessionManager, SessionStream,
-    TcpAcceptor,
+    active_sessions,
+    limiter::{ConcurrencyLimiter, IpConcurrencyLimiter, RateLimiter},
+    stream::TimeoutStream,
+    ServerInstance, SessionData, SessionManager, SessionStream, TcpAcceptor,
 };
 
 impl Server {
@@ -61,7 +65,11 @@ impl Server {
             id: self.id,
             protocol: self.protocol,
             proxy_networks: self.proxy_networks,
+            allowed_networks: self.allowed_networks,
+            denied_networks: self.denied_networks,
             limiter: ConcurrencyLimiter::new(self.max_connections),
+            ip_limiter: self.max_connections_per_ip.map(IpConcurrencyLimiter::new),
+            max_request_size: self.max_request_size,
             acceptor,
             shutdown_rx,
         });
@@ -76,10 +84,19 @@ impl Server {
                 protocol = ?instance.protocol,
                 bind.ip = listener.addr.ip().to_string(),
                 bind.port = listener.addr.port(),
+                backlog = listener.backlog.unwrap_or(1024),
                 tls = is_tls,
                 "Starting listener"
             );
             let local_addr = listener.addr;
+            let proxy_protocol = listener.proxy_protocol;
+            let (read_timeout, write_timeout) = (listener.read_timeout, listener.write_timeout);
+
+            // Each listener has its own accept budget
+            let accept_limiter = listener
+                .accept_rate
+                .clone()
+                .map(|rate| (RateLimiter::new(&rate), rate));
 
             // Obtain TCP options
             let opts = SocketOpts {
@@ -117,7 +134,30 @@ impl Server {
                                     let core = core.as_ref().load();
                                     let enable_acme = (is_https && core.has_acme_tls_providers()).then_some(core.clone());
 
-                                    if has_proxies && instance.proxy_networks.iter().any(|network| network.matches(&remote_addr.ip())) {
+                                    let is_trusted_proxy = has_proxies && instance.proxy_networks.iter().any(|network| network.matches(&remote_addr.ip()));
+
+                                    if accept_limiter.as_ref().is_some_and(|(limiter, rate)| !limiter.is_allowed(rate)) {
+                                        // Dropping the stream closes the connection
+                                        tracing::trace!(
+                                            context = "throttle",
+                                            event = "accept-rate",
+                                            instance = instance.id,
+                                            protocol = ?instance.protocol,
+                                            remote.ip = remote_addr.ip().to_string(),
+                                            remote.port = remote_addr.port(),
+                                            "Dropping connection, accept rate exceeded."
+                                        );
+                                    } else if proxy_protocol && !is_trusted_proxy {
+                                        tracing::debug!(
+                                            context = "listener",
+                                            event = "untrusted-proxy",
+                                            instance = instance.id,
+                                            protocol = ?instance.protocol,
+                                            remote.ip = remote_addr.ip().to_string(),
+                                            remote.port = remote_addr.port(),
+                                            "Dropping connection from untrusted proxy."
+                                        );
+                                    } else if proxy_protocol || is_trusted_proxy {
                                         let instance = instance.clone();
                                         let manager = manager.clone();
 
@@ -131,7 +171,10 @@ impl Server {
                                                                             .proxied_address()
                                                                             .map(|addr| addr.source)
                                                                             .unwrap_or(remote_addr);
-                                                    if let Some(session) = instance.build_session(stream, local_addr, remote_addr, &core) {
+                                                    let stream = TimeoutStream::new(stream, read_timeout, write_timeout);
+                                                    if !instance.is_allowed(remote_addr) {
+                                                        // Dropping the stream closes the connection
+                                                    } else if let Some(session) = instance.build_session(stream, local_addr, Some(remote_addr), &core) {
                                                         // Spawn session
                                                         manager.spawn(session, is_tls, enable_acme);
                                                     }
@@ -146,9 +189,11 @@ impl Server {
                                                 }
                                             }
                                         });
-                                    } else if let Some(session) = instance.build_session(stream, local_addr, remote_addr, &core) {
+                                    } else if !instance.is_allowed(remote_addr) {
+                                        // Dropping the stream closes the connection
+                                    } else if let Some(session) = instance.build_session(TimeoutStream::new(stream, read_timeout, write_timeout), local_addr, Some(remote_addr), &core) {
                                         // Set socket options
-                                        opts.apply(&session.stream);
+                                        opts.apply(session.stream.get_ref());
 
                                         // Spawn session
                                         manager.spawn(session, is_tls, enable_acme);
@@ -169,13 +214,95 @@ impl Server {
                                 instance = instance.id,
                                 protocol = ?instance.protocol,
                                 "Listener shutting down.");
-                            manager.shutdown().await;
                             break;
                         }
                     };
                 }
+
+                // Stop accepting connections and wait for sessions to drain
+                drop(listener);
+                wait_for_close(&mut shutdown_rx).await;
+                manager.shutdown().await;
             });
         }
+
+        // Spawn Unix domain socket listeners
+        #[cfg(unix)]
+        for listener in self.unix_listeners {
+            tracing::info!(
+                id = instance.id,
+                protocol = ?instance.protocol,
+                bind.path = listener.path.display().to_string(),
+                backlog = listener.backlog.unwrap_or(1024),
+                tls = is_tls,
+                "Starting listener"
+            );
+
+            let listener = match listener.listen() {
+                Ok(listener) => listener,
+                Err(err) => {
+                    tracing::error!(
+                        event = "error",
+                        instance = instance.id,
+                        protocol = ?instance.protocol,
+                        reason = %err,
+                        "Failed to bind listener"
+                    );
+                    continue;
+                }
+            };
+
+            let mut shutdown_rx = instance.shutdown_rx.clone();
+            let manager = manager.clone();
+            let instance = instance.clone();
+            let core = core.clone();
+            tokio::spawn(async move {
+                loop {
+                    tokio::select! {
+                        stream = listener.accept() => {
+                            match stream {
+                                Ok((stream, _)) => {
+                                    let core = core.as_ref().load();
+                                    if let Some(session) = instance.build_session(stream, UNIX_PEER_ADDR, None, &core) {
+                                        manager.spawn(session, is_tls, None);
+                                    }
+                                }
+                                Err(err) => {
+                                    tracing::trace!(context = "io",
+                                                    event = "error",
+                                                    instance = instance.id,
+                                                    protocol = ?instance.protocol,
+                                                    "Failed to accept Unix domain socket connection: {}", err);
+                                }
+                            }
+                        },
+                        _ = shutdown_rx.changed() => {
+                            tracing::debug!(
+                                event = "shutdown",
+                                instance = instance.id,
+                                protocol = ?instance.protocol,
+                                "Listener shutting down.");
+                            break;
+                        }
+                    };
+                }
+
+                // Stop accepting connections and wait for sessions to drain
+                drop(listener);
+                wait_for_close(&mut shutdown_rx).await;
+                manager.shutdown().await;
+            });
+        }
+    }
+}
+
+async fn wait_for_close(shutdown_rx: &mut watch::Receiver<bool>) {
+    // A false value requests draining, true forces the remaining sessions to close
+    loop {
+        let is_closing = *shutdown_rx.borrow();
+        if is_closing || shutdown_rx.changed().await.is_err() {
+            break;
+        }
     }
 }
 
@@ -184,19 +311,24 @@ trait BuildSession {
         &self,
         stream: T,
         local_addr: SocketAddr,
-        remote_addr: SocketAddr,
+        remote_addr: Option<SocketAddr>,
         core: &Core,
     ) -> Option<SessionData<T>>;
 }
 
 impl BuildSession for Arc<ServerInstance> {
+    // A missing remote address denotes a Unix domain socket peer, which is only
+    // subject to the listener's overall connection limit
     fn build_session<T: SessionStream>(
         &self,
         stream: T,
         local_addr: SocketAddr,
-        remote_addr: SocketAddr,
+        remote_addr: Option<SocketAddr>,
         core: &Core,
     ) -> Option<SessionData<T>> {
+        let is_unix = remote_addr.is_none();
+        let remote_addr = remote_addr.unwrap_or(UNIX_PEER_ADDR);
+
         // Convert mapped IPv6 addresses to IPv4
         let remote_ip = match remote_addr.ip() {
             IpAddr::V6(ip) => ip
@@ -208,7 +340,7 @@ impl BuildSession for Arc<ServerInstance> {
         let remote_port = remote_addr.port();
 
         // Check if blocked
-        if core.is_ip_blocked(&remote_ip) {
+        if !is_unix && core.is_ip_blocked(&remote_ip) {
             tracing::debug!(
                 context = "listener",
                 event = "blocked",
@@ -220,7 +352,28 @@ impl BuildSession for Arc<ServerInstance> {
             );
             None
         } else if let Some(in_flight) = self.limiter.is_allowed() {
-            // Enforce concurrency
+            // Enforce per-IP concurrency
+            let in_flight = match self.ip_limiter.as_ref().filter(|_| !is_unix) {
+                Some(ip_limiter) => match ip_limiter.is_allowed(remote_ip) {
+                    Some(ip_in_flight) => in_flight.with_ip_limit(ip_in_flight),
+                    None => {
+                        tracing::info!(
+                            context = "throttle",
+                            event = "too-many-requests",
+                            instance = self.id,
+                            protocol = ?self.protocol,
+                            remote.ip = remote_ip.to_string(),
+                            remote.port = remote_port,
+                            max_concurrent_per_ip = ip_limiter.max_concurrent,
+                            "Too many concurrent connections from IP."
+                        );
+                        self.reject(stream);
+                        return None;
+                    }
+                },
+                None => in_flight,
+            };
+
             SessionData {
                 stream,
                 in_flight,
@@ -250,11 +403,67 @@ impl BuildSession for Arc<ServerInstance> {
                 max_concurrent = self.limiter.max_concurrent,
                 "Too many concurrent connections."
             );
+
+            self.reject(stream);
             None
         }
     }
 }
 
+impl ServerInstance {
+    // Access lists are checked against the peer address, or the proxied source
+    // address when the PROXY protocol is used. Denied networks take precedence,
+    // and an empty allow list allows every address.
+    fn is_allowed(&self, remote_addr: SocketAddr) -> bool {
+        if self.allowed_networks.is_empty() && self.denied_networks.is_empty() {
+            return true;
+        }
+
+        let remote_ip = match remote_addr.ip() {
+            IpAddr::V6(ip) => ip
+                .to_ipv4_mapped()
+                .map(IpAddr::V4)
+                .unwrap_or(IpAddr::V6(ip)),
+            remote_ip => remote_ip,
+        };
+        let is_allowed = !self
+            .denied_networks
+            .iter()
+            .any(|network| network.matches(&remote_ip))
+            && (self.allowed_networks.is_empty()
+                || self
+                    .allowed_networks
+                    .iter()
+                    .any(|network| network.matches(&remote_ip)));
+        if !is_allowed {
+            tracing::debug!(
+                context = "listener",
+                event = "denied",
+                instance = self.id,
+                protocol = ?self.protocol,
+                remote.ip = remote_ip.to_string(),
+                remote.port = remote_addr.port(),
+                "Dropping connection from IP not allowed on this listener."
+            );
+        }
+
+        is_allowed
+    }
+
+    fn reject<T: SessionStream>(&self, mut stream: T) {
+        // Let the client know why the connection is being closed, TLS clients
+        // would not understand a plain text response
+        if !matches!(self.acceptor, TcpAcceptor::Tls { implicit: true, .. }) {
+            let response = self.protocol.too_many_connections();
+            tokio::spawn(async move {
+                if stream.write_all(response).await.is_ok() {
+                    let _ = stream.shutdown().await;
+                }
+            });
+        }
+    }
+}
+
 pub struct SocketOpts {
     pub nodelay: bool,
     pub ttl: Option<u32>,
@@ -296,17 +505,41 @@ impl SocketOpts {
 }
 
 impl Servers {
-    pub fn bind_and_drop_priv(&self, config: &mut Config) {
+    pub async fn bind_and_drop_priv(&self, config: &mut Config) {
         // Bind as root
         for server in &self.servers {
+            let id = server.id.as_str();
+            let retries = config
+                .property_or_else::<u32>(
+                    ("server.listener", id, "bind-retries"),
+                    "server.bind-retries",
+                    "0",
+                )
+                .unwrap_or(0);
+            let retry_delay = config
+                .property_or_else::<Duration>(
+                    ("server.listener", id, "bind-retry-delay"),
+                    "server.bind-retry-delay",
+                    "1s",
+                )
+                .unwrap_or(Duration::from_secs(1));
             for listener in &server.listeners {
-                if let Err(err) = listener.socket.bind(listener.addr) {
+                if let Err(err) = listener.bind(retries, retry_delay).await {
                     config.new_build_error(
                         format!("server.listener.{}", server.id),
                         format!("Failed to bind to {}: {}", listener.addr, err),
                     );
                 }
             }
+            #[cfg(unix)]
+            for listener in &server.unix_listeners {
+                if let Err(err) = listener.bind() {
+                    config.new_build_error(
+                        format!("server.listener.{}", server.id),
+                        format!("Failed to bind to {:?}: {}", listener.path, err),
+                    );
+                }
+            }
         }
 
         // Drop privileges
@@ -323,24 +556,101 @@ impl Servers {
     }
 
     pub fn spawn(
-        mut self,
+        &mut self,
         spawn: impl Fn(Server, TcpAcceptor, watch::Receiver<bool>),
-    ) -> (watch::Sender<bool>, watch::Receiver<bool>) {
+    ) -> watch::Receiver<bool> {
         // Spawn listeners
-        let (shutdown_tx, shutdown_rx) = watch::channel(false);
-        for server in self.servers {
+        for server in std::mem::take(&mut self.servers) {
             let acceptor = self
                 .tcp_acceptors
                 .remove(&server.id)
                 .unwrap_or(TcpAcceptor::Plain);
 
-            spawn(server, acceptor, shutdown_rx.clone());
+            spawn(server, acceptor, self.shutdown_tx.subscribe());
+        }
+
+        // Refresh OCSP responses until shutdown
+        for (ocsp, resolver) in std::mem::take(&mut self.ocsp_staplers) {
+            ocsp.spawn(resolver, self.shutdown_tx.subscribe());
         }
-        (shutdown_tx, shutdown_rx)
+
+        self.shutdown_tx.subscribe()
+    }
+
+    pub async fn shutdown(&self, drain_timeout: Duration) {
+        // Stop accepting new connections
+        self.shutdown_tx.send(false).ok();
+
+        // Wait for in-flight sessions to finish
+        let deadline = Instant::now() + drain_timeout;
+        while active_sessions() > 0 && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        // Close any remaining sessions
+        self.shutdown_tx.send(true).ok();
     }
 }
 
 impl Listener {
+    // Addresses still held by another process, such as the previous instance during
+    // a restart, are retried with the delay doubling after each attempt. Any other
+    // error is returned right away.
+    pub async fn bind(&self, retries: u32, delay: Duration) -> std::io::Result<()> {
+        let mut delay = delay;
+        let mut attempt = 0;
+        loop {
+            match self.socket.bind(self.addr) {
+                Err(err) if err.kind() == std::io::ErrorKind::AddrInUse && attempt < retries => {
+                    attempt += 1;
+                    tracing::warn!(
+                        context = "listener",
+                        event = "bind-retry",
+                        addr = %self.addr,
+                        attempt = attempt,
+                        retries = retries,
+                        "Address in use, retrying bind in {:?}.",
+                        delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay = delay.saturating_mul(2);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    pub fn set_keepalive(&self) -> std::io::Result<()> {
+        // Accepted sockets inherit the keepalive settings of the listener
+        if let Some(keepalive) = self.keepalive {
+            SockRef::from(&self.socket).set_tcp_keepalive(&TcpKeepalive::new().with_time(keepalive))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn set_tcp_options(&self) -> std::io::Result<()> {
+        // Also applied to each accepted stream, set here so they can be read back
+        let socket = SockRef::from(&self.socket);
+        socket.set_nodelay(self.nodelay)?;
+        if let Some(ttl) = self.ttl {
+            socket.set_ttl(ttl)?;
+        }
+        if self.linger.is_some() {
+            socket.set_linger(self.linger)?;
+        }
+        Ok(())
+    }
+
+    pub fn effective_options(&self) -> std::io::Result<ListenerOptions> {
+        let socket = SockRef::from(&self.socket);
+        Ok(ListenerOptions {
+            ttl: socket.ttl()?,
+            linger: socket.linger()?,
+            nodelay: socket.nodelay()?,
+        })
+    }
+
     pub fn listen(self) -> Result<TcpListener, String> {
         self.socket
             .listen(self.backlog.unwrap_or(1024))
@@ -348,6 +658,36 @@ impl Listener {
     }
 }
 
+#[cfg(unix)]
+impl crate::config::server::UnixSocketListener {
+    pub fn bind(&self) -> std::io::Result<()> {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+        // Remove stale sockets left behind by a previous run
+        if std::fs::symlink_metadata(&self.path).is_ok_and(|m| m.file_type().is_socket()) {
+            std::fs::remove_file(&self.path)?;
+        }
+
+        self.socket.bind(&socket2::SockAddr::unix(&self.path)?)?;
+        if let Some(permissions) = self.permissions {
+            std::fs::set_permissions(&self.path, std::fs::Permissions::from_mode(permissions))?;
+        }
+        Ok(())
+    }
+
+    pub fn listen(self) -> Result<tokio::net::UnixListener, String> {
+        let path = self.path;
+        let socket = self.socket;
+        socket
+            .listen(self.backlog.unwrap_or(1024) as i32)
+            .and_then(|_| socket.set_nonblocking(true))
+            .and_then(|_| {
+                tokio::net::UnixListener::from_std(std::os::unix::net::UnixListener::from(socket))
+            })
+            .map_err(|err| format!("Failed to listen on {:?}: {}", path, err))
+    }
+}
+
 impl ServerInstance {
     pub async fn tls_accept<T: SessionStream>(
         &self,
@@ -21,7 +21,14 @@
  * for more details.
 */
 
-use std::{borrow::Cow, net::IpAddr, sync::Arc};
+use std::{
+    borrow::Cow,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
 use rustls::ServerConfig;
 use std::fmt::Debug;
@@ -38,24 +45,57 @@ use crate::{
     Core,
 };
 
-use self::limiter::{ConcurrencyLimiter, InFlight};
+use self::limiter::{ConcurrencyLimiter, InFlight, IpConcurrencyLimiter};
 
 pub mod acme;
 pub mod blocked;
 pub mod limiter;
 pub mod listen;
+pub mod ocsp;
 pub mod stream;
 pub mod tls;
 
+// Sessions currently being handled across all listeners
+static ACTIVE_SESSIONS: AtomicU64 = AtomicU64::new(0);
+
+pub fn active_sessions() -> u64 {
+    ACTIVE_SESSIONS.load(Ordering::Relaxed)
+}
+
+struct ActiveSession;
+
+impl ActiveSession {
+    fn new() -> Self {
+        ACTIVE_SESSIONS.fetch_add(1, Ordering::Relaxed);
+        ActiveSession
+    }
+}
+
+impl Drop for ActiveSession {
+    fn drop(&mut self) {
+        ACTIVE_SESSIONS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
 pub struct ServerInstance {
     pub id: String,
     pub protocol: ServerProtocol,
     pub acceptor: TcpAcceptor,
     pub limiter: ConcurrencyLimiter,
+    pub ip_limiter: Option<IpConcurrencyLimiter>,
     pub proxy_networks: Vec<IpAddrMask>,
+    pub allowed_networks: Vec<IpAddrMask>,
+    pub denied_networks: Vec<IpAddrMask>,
+    pub max_request_size: Option<u64>,
     pub shutdown_rx: watch::Receiver<bool>,
 }
 
+impl ServerInstance {
+    pub fn num_connections(&self) -> u64 {
+        self.limiter.num_concurrent()
+    }
+}
+
 #[derive(Default)]
 pub enum TcpAcceptor {
     Tls {
@@ -102,8 +142,10 @@ pub trait SessionManager: Sync + Send + 'static + Clone {
         acme_core: Option<Arc<Core>>,
     ) {
         let manager = self.clone();
+        let active_session = ActiveSession::new();
 
         tokio::spawn(async move {
+            let _active_session = active_session;
             if is_tls {
                 match session
                     .instance
@@ -0,0 +1,711 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use ahash::AHashMap;
+use arc_swap::ArcSwap;
+use reqwest::header::CONTENT_TYPE;
+use ring::signature::{self, UnparsedPublicKey, VerificationAlgorithm};
+use rustls::sign::CertifiedKey;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use tokio::sync::watch;
+use x509_parser::{
+    certificate::X509Certificate,
+    der_parser::asn1_rs::FromDer,
+    extensions::{GeneralName, ParsedExtension},
+    x509::SubjectPublicKeyInfo,
+};
+
+use super::tls::CertificateResolver;
+
+const OID_OCSP: &str = "1.3.6.1.5.5.7.48.1";
+const OID_OCSP_BASIC: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+const OID_SHA1: &[u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+const OID_SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+const OID_SHA1_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x05];
+const OID_SHA256_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b];
+const OID_SHA384_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0c];
+const OID_SHA512_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0d];
+const OID_ECDSA_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+const OID_ECDSA_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03];
+const OID_ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+const OID_CURVE_P256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const OID_CURVE_P384: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x22];
+
+pub struct OcspStapler {
+    pub path: Option<PathBuf>,
+    pub refresh: Duration,
+    pub timeout: Duration,
+    // Stapled copies of the certificates, keyed by end entity certificate
+    staples: ArcSwap<AHashMap<Vec<u8>, Stapled>>,
+}
+
+#[derive(Clone)]
+struct Stapled {
+    cert: Arc<CertifiedKey>,
+    expires: u64,
+}
+
+impl OcspStapler {
+    pub fn new(path: Option<PathBuf>, refresh: Duration, timeout: Duration) -> Self {
+        OcspStapler {
+            path,
+            refresh,
+            timeout,
+            staples: ArcSwap::from_pointee(AHashMap::new()),
+        }
+    }
+
+    pub fn staple(&self, cert: Arc<CertifiedKey>) -> Arc<CertifiedKey> {
+        // Certificates without a valid response are served without a staple
+        cert.end_entity_cert()
+            .ok()
+            .and_then(|ee| {
+                self.staples
+                    .load()
+                    .get(ee.as_ref())
+                    .filter(|stapled| stapled.expires > now())
+                    .map(|stapled| stapled.cert.clone())
+            })
+            .unwrap_or(cert)
+    }
+
+    pub fn spawn(
+        self: Arc<Self>,
+        resolver: Arc<CertificateResolver>,
+        mut shutdown_rx: watch::Receiver<bool>,
+    ) {
+        tokio::spawn(async move {
+            loop {
+                self.update(&resolver).await;
+                tokio::select! {
+                    _ = tokio::time::sleep(self.refresh) => {}
+                    _ = shutdown_rx.changed() => break,
+                }
+            }
+        });
+    }
+
+    pub async fn update(&self, resolver: &CertificateResolver) {
+        // Collect the certificates served by this listener
+        let mut certs = resolver.sni.values().cloned().collect::<Vec<_>>();
+        certs.extend(
+            resolver
+                .core
+                .load()
+                .tls
+                .certificates
+                .load()
+                .values()
+                .cloned(),
+        );
+
+        let now = now();
+        let mut staples = AHashMap::new();
+        for cert in certs {
+            let ee = match cert.end_entity_cert() {
+                Ok(ee) => ee.as_ref().to_vec(),
+                Err(_) => continue,
+            };
+            if staples.contains_key(&ee) {
+                continue;
+            }
+
+            match self.fetch(&cert).await {
+                Ok((response, expires)) => {
+                    staples.insert(
+                        ee,
+                        Stapled {
+                            cert: Arc::new(CertifiedKey {
+                                cert: cert.cert.clone(),
+                                key: cert.key.clone(),
+                                ocsp: Some(response),
+                            }),
+                            expires,
+                        },
+                    );
+                }
+                Err(err) => {
+                    tracing::debug!(
+                        context = "ocsp",
+                        event = "error",
+                        reason = %err,
+                        "Failed to refresh OCSP response."
+                    );
+
+                    // Keep serving the previous response until it expires
+                    if let Some(stapled) = self
+                        .staples
+                        .load()
+                        .get(&ee)
+                        .filter(|stapled| stapled.expires > now)
+                    {
+                        staples.insert(ee, stapled.clone());
+                    }
+                }
+            }
+        }
+
+        self.staples.store(Arc::new(staples));
+    }
+
+    async fn fetch(&self, cert: &CertifiedKey) -> Result<(Vec<u8>, u64), String> {
+        let ee = cert
+            .end_entity_cert()
+            .map_err(|err| format!("Failed to obtain end entity cert: {err}"))?;
+        let (_, leaf) = X509Certificate::from_der(ee.as_ref())
+            .map_err(|err| format!("Failed to parse end entity cert: {err}"))?;
+
+        // The issuer is needed to match the response and verify its signature
+        let issuer = cert
+            .cert
+            .get(1)
+            .ok_or_else(|| "Certificate chain does not include the issuer".to_string())?;
+        let (_, issuer) = X509Certificate::from_der(issuer.as_ref())
+            .map_err(|err| format!("Failed to parse issuer cert: {err}"))?;
+
+        let response = if let Some(path) = &self.path {
+            tokio::fs::read(path)
+                .await
+                .map_err(|err| format!("Failed to read OCSP response from {path:?}: {err}"))?
+        } else {
+            let url = responder_url(&leaf)
+                .ok_or_else(|| "Certificate does not include an OCSP responder".to_string())?;
+
+            let response = reqwest::Client::builder()
+                .timeout(self.timeout)
+                .build()
+                .map_err(|err| format!("Failed to build HTTP client: {err}"))?
+                .post(url)
+                .header(CONTENT_TYPE, "application/ocsp-request")
+                .body(build_request(&leaf, &issuer))
+                .send()
+                .await
+                .map_err(|err| format!("OCSP request to {url} failed: {err}"))?;
+            if !response.status().is_success() {
+                return Err(format!(
+                    "OCSP responder {url} returned {}",
+                    response.status()
+                ));
+            }
+            response
+                .bytes()
+                .await
+                .map_err(|err| format!("Failed to read OCSP response from {url}: {err}"))?
+                .to_vec()
+        };
+
+        match parse_response(&response, &leaf, &issuer) {
+            Some(expires) if expires > now() => Ok((response, expires)),
+            Some(_) => Err("OCSP response has expired".to_string()),
+            None => Err("Invalid or non-good OCSP response for certificate".to_string()),
+        }
+    }
+}
+
+fn responder_url<'x>(cert: &'x X509Certificate<'x>) -> Option<&'x str> {
+    cert.extensions().iter().find_map(|ext| {
+        if let ParsedExtension::AuthorityInfoAccess(aia) = ext.parsed_extension() {
+            aia.accessdescs
+                .iter()
+                .find_map(|desc| match desc.access_location {
+                    GeneralName::URI(uri) if desc.access_method.to_id_string() == OID_OCSP => {
+                        Some(uri)
+                    }
+                    _ => None,
+                })
+        } else {
+            None
+        }
+    })
+}
+
+fn build_request(leaf: &X509Certificate<'_>, issuer: &X509Certificate<'_>) -> Vec<u8> {
+    let (name_hash, key_hash) = cert_id_hashes(leaf, issuer, OID_SHA1).unwrap_or_default();
+
+    // CertID ::= SEQUENCE { hashAlgorithm, issuerNameHash, issuerKeyHash, serialNumber }
+    let mut cert_id = der(0x30, &[der(0x06, OID_SHA1), der(0x05, &[])].concat());
+    cert_id.extend(der(0x04, &name_hash));
+    cert_id.extend(der(0x04, &key_hash));
+    cert_id.extend(der(0x02, leaf.raw_serial()));
+
+    // OCSPRequest ::= SEQUENCE { TBSRequest { requestList { Request { CertID } } } }
+    der(
+        0x30,
+        &der(0x30, &der(0x30, &der(0x30, &der(0x30, &cert_id)))),
+    )
+}
+
+// Hashes of the issuer name and key identifying the issuer in a CertID
+fn cert_id_hashes(
+    leaf: &X509Certificate<'_>,
+    issuer: &X509Certificate<'_>,
+    algorithm: &[u8],
+) -> Option<(Vec<u8>, Vec<u8>)> {
+    let name = leaf.issuer().as_raw();
+    let key = issuer.public_key().subject_public_key.data.as_ref();
+    match algorithm {
+        OID_SHA1 => Some((Sha1::digest(name).to_vec(), Sha1::digest(key).to_vec())),
+        OID_SHA256 => Some((Sha256::digest(name).to_vec(), Sha256::digest(key).to_vec())),
+        _ => None,
+    }
+}
+
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes = len.to_be_bytes();
+        let bytes = &bytes[bytes.iter().position(|&b| b != 0).unwrap_or(0)..];
+        out.push(0x80 | bytes.len() as u8);
+        out.extend_from_slice(bytes);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+// Returns the expiration of a good response for the certificate, which must be
+// signed by its issuer or by a responder the issuer delegated OCSP signing to
+fn parse_response(
+    response: &[u8],
+    leaf: &X509Certificate<'_>,
+    issuer: &X509Certificate<'_>,
+) -> Option<u64> {
+    // OCSPResponse ::= SEQUENCE { responseStatus, responseBytes [0] EXPLICIT }
+    let (response, _) = read(response, 0x30)?;
+    let (status, response) = read(response, 0x0a)?;
+    if status != [0] {
+        return None;
+    }
+    let (response_bytes, _) = read(response, 0xa0)?;
+    let (response_bytes, _) = read(response_bytes, 0x30)?;
+    let (oid, response_bytes) = read(response_bytes, 0x06)?;
+    if oid != OID_OCSP_BASIC {
+        return None;
+    }
+    let (basic, _) = read(response_bytes, 0x04)?;
+
+    // BasicOCSPResponse ::= SEQUENCE { tbsResponseData, signatureAlgorithm, signature, certs [0] EXPLICIT }
+    let (basic, _) = read(basic, 0x30)?;
+    let (mut data, rest) = read(basic, 0x30)?;
+    let tbs = &basic[..basic.len() - rest.len()];
+    let (algorithm, rest) = read(rest, 0x30)?;
+    let (algorithm, _) = read(algorithm, 0x06)?;
+    let (signature, rest) = read(rest, 0x03)?;
+    let signature = signature.strip_prefix(&[0])?;
+    let certs = read(rest, 0xa0)
+        .and_then(|(certs, _)| read(certs, 0x30))
+        .map(|(certs, _)| certs)
+        .unwrap_or_default();
+    if !is_signed_by_responder(tbs, algorithm, signature, certs, issuer) {
+        return None;
+    }
+
+    if data.first() == Some(&0xa0) {
+        data = read(data, 0xa0)?.1;
+    }
+    data = read_any(data)?.2;
+    data = read(data, 0x18)?.1;
+    let (mut responses, _) = read(data, 0x30)?;
+
+    while !responses.is_empty() {
+        let (single, rest) = read(responses, 0x30)?;
+        responses = rest;
+
+        // SingleResponse ::= SEQUENCE { certID, certStatus, thisUpdate, nextUpdate [0] }
+        let (cert_id, single) = read(single, 0x30)?;
+        if !matches_cert_id(cert_id, leaf, issuer)? {
+            continue;
+        }
+
+        // Only responses reporting the certificate as good are stapled
+        let (cert_status, _, single) = read_any(single)?;
+        if cert_status != 0x80 {
+            return None;
+        }
+        let (_, single) = read(single, 0x18)?;
+        let (next_update, _) = read(single, 0xa0).unwrap_or_default();
+
+        // Responses without a next update are considered valid until the next refresh
+        return match read(next_update, 0x18) {
+            Some((time, _)) => parse_time(time),
+            None => Some(u64::MAX),
+        };
+    }
+
+    None
+}
+
+// CertID ::= SEQUENCE { hashAlgorithm, issuerNameHash, issuerKeyHash, serialNumber }
+fn matches_cert_id(
+    cert_id: &[u8],
+    leaf: &X509Certificate<'_>,
+    issuer: &X509Certificate<'_>,
+) -> Option<bool> {
+    let (algorithm, cert_id) = read(cert_id, 0x30)?;
+    let (algorithm, _) = read(algorithm, 0x06)?;
+    let (name_hash, cert_id) = read(cert_id, 0x04)?;
+    let (key_hash, cert_id) = read(cert_id, 0x04)?;
+    let (serial, _) = read(cert_id, 0x02)?;
+
+    Some(
+        serial == leaf.raw_serial()
+            && cert_id_hashes(leaf, issuer, algorithm).is_some_and(|(name, key)| {
+                name_hash == name.as_slice() && key_hash == key.as_slice()
+            }),
+    )
+}
+
+fn is_signed_by_responder(
+    tbs: &[u8],
+    algorithm: &[u8],
+    signature: &[u8],
+    mut certs: &[u8],
+    issuer: &X509Certificate<'_>,
+) -> bool {
+    if verify_signature(issuer.public_key(), algorithm, tbs, signature) {
+        return true;
+    }
+
+    // Delegated responders are issued by the issuer for OCSP signing only
+    while let Ok((rest, responder)) = X509Certificate::from_der(certs) {
+        certs = rest;
+        if responder.issuer().as_raw() == issuer.subject().as_raw()
+            && responder.validity().is_valid()
+            && responder
+                .extended_key_usage()
+                .ok()
+                .flatten()
+                .is_some_and(|eku| eku.value.ocsp_signing)
+            && verify_signature(
+                issuer.public_key(),
+                responder.signature_algorithm.algorithm.as_bytes(),
+                responder.tbs_certificate.as_ref(),
+                responder.signature_value.data.as_ref(),
+            )
+            && verify_signature(responder.public_key(), algorithm, tbs, signature)
+        {
+            return true;
+        }
+    }
+
+    false
+}
+
+fn verify_signature(
+    key: &SubjectPublicKeyInfo<'_>,
+    algorithm: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> bool {
+    let curve = key
+        .algorithm
+        .parameters
+        .as_ref()
+        .and_then(|params| params.as_oid().ok());
+    let curve = curve.as_ref().map(|curve| curve.as_bytes());
+    let algorithm: &dyn VerificationAlgorithm = match (algorithm, curve) {
+        (OID_SHA1_RSA, _) => &signature::RSA_PKCS1_2048_8192_SHA1_FOR_LEGACY_USE_ONLY,
+        (OID_SHA256_RSA, _) => &signature::RSA_PKCS1_2048_8192_SHA256,
+        (OID_SHA384_RSA, _) => &signature::RSA_PKCS1_2048_8192_SHA384,
+        (OID_SHA512_RSA, _) => &signature::RSA_PKCS1_2048_8192_SHA512,
+        (OID_ECDSA_SHA256, Some(OID_CURVE_P256)) => &signature::ECDSA_P256_SHA256_ASN1,
+        (OID_ECDSA_SHA256, Some(OID_CURVE_P384)) => &signature::ECDSA_P384_SHA256_ASN1,
+        (OID_ECDSA_SHA384, Some(OID_CURVE_P256)) => &signature::ECDSA_P256_SHA384_ASN1,
+        (OID_ECDSA_SHA384, Some(OID_CURVE_P384)) => &signature::ECDSA_P384_SHA384_ASN1,
+        (OID_ED25519, _) => &signature::ED25519,
+        _ => return false,
+    };
+
+    UnparsedPublicKey::new(algorithm, key.subject_public_key.data.as_ref())
+        .verify(message, signature)
+        .is_ok()
+}
+
+fn read(data: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    let (found, content, rest) = read_any(data)?;
+    (found == tag).then_some((content, rest))
+}
+
+fn read_any(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, data) = data.split_first()?;
+    let (&len, mut data) = data.split_first()?;
+    let len = if len & 0x80 == 0 {
+        len as usize
+    } else {
+        let num_bytes = (len & 0x7f) as usize;
+        if num_bytes == 0 || num_bytes > std::mem::size_of::<usize>() || data.len() < num_bytes {
+            return None;
+        }
+        let (bytes, rest) = data.split_at(num_bytes);
+        data = rest;
+        bytes.iter().fold(0usize, |acc, &b| (acc << 8) | b as usize)
+    };
+    (data.len() >= len).then(|| (tag, &data[..len], &data[len..]))
+}
+
+fn parse_time(time: &[u8]) -> Option<u64> {
+    // GeneralizedTime, YYYYMMDDHHMMSS[.fff]Z
+    let time = std::str::from_utf8(time.get(..14)?).ok()?;
+    chrono::NaiveDateTime::parse_from_str(time, "%Y%m%d%H%M%S")
+        .ok()
+        .map(|dt| dt.and_utc().timestamp().max(0) as u64)
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::UNIX_EPOCH
+        .elapsed()
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod test {
+    use rcgen::{
+        BasicConstraints, Certificate, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa,
+    };
+    use ring::{
+        rand::SystemRandom,
+        signature::{EcdsaKeyPair, ECDSA_P256_SHA256_ASN1_SIGNING},
+    };
+
+    use super::*;
+
+    fn build_cert(name: &str, is_ca: bool, eku: Option<ExtendedKeyUsagePurpose>) -> Certificate {
+        let mut params = CertificateParams::new(vec![format!("{name}.example.org")]);
+        params.distinguished_name.push(DnType::CommonName, name);
+        if is_ca {
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        }
+        params.extended_key_usages.extend(eku);
+        Certificate::from_params(params).unwrap()
+    }
+
+    fn sign(signer: &Certificate, message: &[u8]) -> Vec<u8> {
+        let rng = SystemRandom::new();
+        EcdsaKeyPair::from_pkcs8(
+            &ECDSA_P256_SHA256_ASN1_SIGNING,
+            &signer.serialize_private_key_der(),
+            &rng,
+        )
+        .unwrap()
+        .sign(&rng, message)
+        .unwrap()
+        .as_ref()
+        .to_vec()
+    }
+
+    fn build_cert_id(
+        algorithm: &[u8],
+        name_hash: &[u8],
+        key_hash: &[u8],
+        serial: &[u8],
+    ) -> Vec<u8> {
+        der(
+            0x30,
+            &[
+                der(0x30, &[der(0x06, algorithm), der(0x05, &[])].concat()),
+                der(0x04, name_hash),
+                der(0x04, key_hash),
+                der(0x02, serial),
+            ]
+            .concat(),
+        )
+    }
+
+    fn build_response(
+        status: u8,
+        cert_id: Vec<u8>,
+        cert_status: Vec<u8>,
+        next_update: &[u8],
+        signer: &Certificate,
+        certs: &[Vec<u8>],
+    ) -> Vec<u8> {
+        let single = der(
+            0x30,
+            &[
+                cert_id,
+                cert_status,
+                der(0x18, b"20240101000000Z"),
+                der(0xa0, &der(0x18, next_update)),
+            ]
+            .concat(),
+        );
+        let data = der(
+            0x30,
+            &[
+                der(0xa1, &der(0x30, &[])),
+                der(0x18, b"20240101000000Z"),
+                der(0x30, &single),
+            ]
+            .concat(),
+        );
+        let mut basic = [
+            der(0x30, &der(0x06, OID_ECDSA_SHA256)),
+            der(0x03, &[&[0u8][..], &sign(signer, &data)].concat()),
+        ]
+        .concat();
+        if !certs.is_empty() {
+            basic.extend(der(0xa0, &der(0x30, &certs.concat())));
+        }
+        let basic = der(0x30, &[data, basic].concat());
+        der(
+            0x30,
+            &[
+                der(0x0a, &[status]),
+                der(
+                    0xa0,
+                    &der(
+                        0x30,
+                        &[der(0x06, OID_OCSP_BASIC), der(0x04, &basic)].concat(),
+                    ),
+                ),
+            ]
+            .concat(),
+        )
+    }
+
+    #[test]
+    fn ocsp_response() {
+        let ca = build_cert("Test CA", true, None);
+        let other_ca = build_cert("Other CA", true, None);
+        let leaf = build_cert("leaf", false, None)
+            .serialize_der_with_signer(&ca)
+            .unwrap();
+        let ca_der = ca.serialize_der().unwrap();
+        let (_, leaf) = X509Certificate::from_der(&leaf).unwrap();
+        let (_, issuer) = X509Certificate::from_der(&ca_der).unwrap();
+        let serial = leaf.raw_serial();
+        let cert_id = |algorithm: &[u8]| {
+            let (name_hash, key_hash) = cert_id_hashes(&leaf, &issuer, algorithm).unwrap();
+            build_cert_id(algorithm, &name_hash, &key_hash, serial)
+        };
+        let good = || der(0x80, &[]);
+        let parse = |response: &[u8]| parse_response(response, &leaf, &issuer);
+
+        let response = build_response(0, cert_id(OID_SHA1), good(), b"20990101000000Z", &ca, &[]);
+        assert_eq!(parse(&response), Some(4070908800));
+        assert_eq!(
+            parse(&build_response(
+                0,
+                cert_id(OID_SHA256),
+                good(),
+                b"20240102030405.123Z",
+                &ca,
+                &[]
+            )),
+            Some(1704164645)
+        );
+        assert_eq!(
+            parse(&build_response(
+                1,
+                cert_id(OID_SHA1),
+                good(),
+                b"20990101000000Z",
+                &ca,
+                &[]
+            )),
+            None
+        );
+        assert_eq!(parse(&response[..response.len() - 1]), None);
+
+        // The whole CertID has to match, not only the serial
+        let (name_hash, key_hash) = cert_id_hashes(&leaf, &issuer, OID_SHA1).unwrap();
+        for cert_id in [
+            build_cert_id(OID_SHA1, &name_hash, &key_hash, &[0x0a, 0x01]),
+            build_cert_id(OID_SHA1, &[0u8; 20], &key_hash, serial),
+            build_cert_id(OID_SHA1, &name_hash, &[0u8; 20], serial),
+            build_cert_id(OID_ECDSA_SHA256, &name_hash, &key_hash, serial),
+        ] {
+            assert_eq!(
+                parse(&build_response(
+                    0,
+                    cert_id,
+                    good(),
+                    b"20990101000000Z",
+                    &ca,
+                    &[]
+                )),
+                None
+            );
+        }
+
+        // Revoked and unknown certificates are never stapled
+        for cert_status in [der(0xa1, &der(0x18, b"20240101000000Z")), der(0x82, &[])] {
+            assert_eq!(
+                parse(&build_response(
+                    0,
+                    cert_id(OID_SHA1),
+                    cert_status,
+                    b"20990101000000Z",
+                    &ca,
+                    &[]
+                )),
+                None
+            );
+        }
+
+        // Responses signed by anyone else than the issuer or a delegated responder are rejected
+        let responder = build_cert(
+            "responder",
+            false,
+            Some(ExtendedKeyUsagePurpose::OcspSigning),
+        );
+        let unauthorized = build_cert("unauthorized", false, None);
+        for (signer, certs, expected) in [
+            (&other_ca, vec![], None),
+            (
+                &responder,
+                vec![responder.serialize_der_with_signer(&ca).unwrap()],
+                Some(4070908800),
+            ),
+            (&responder, vec![], None),
+            (
+                &responder,
+                vec![responder.serialize_der_with_signer(&other_ca).unwrap()],
+                None,
+            ),
+            (
+                &unauthorized,
+                vec![unauthorized.serialize_der_with_signer(&ca).unwrap()],
+                None,
+            ),
+        ] {
+            assert_eq!(
+                parse(&build_response(
+                    0,
+                    cert_id(OID_SHA1),
+                    good(),
+                    b"20990101000000Z",
+                    signer,
+                    &certs
+                )),
+                expected
+            );
+        }
+    }
+}
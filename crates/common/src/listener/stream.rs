@@ -21,12 +21,19 @@
  * for more details.
 */
 
-use std::borrow::Cow;
+use std::{
+    borrow::Cow,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
 
 use proxy_header::io::ProxiedStream;
 use tokio::{
-    io::{AsyncRead, AsyncWrite},
+    io::{AsyncRead, AsyncWrite, ReadBuf},
     net::TcpStream,
+    time::Sleep,
 };
 use tokio_rustls::server::TlsStream;
 
@@ -42,6 +49,17 @@ impl SessionStream for TcpStream {
     }
 }
 
+#[cfg(unix)]
+impl SessionStream for tokio::net::UnixStream {
+    fn is_tls(&self) -> bool {
+        false
+    }
+
+    fn tls_version_and_cipher(&self) -> (Cow<'static, str>, Cow<'static, str>) {
+        (Cow::Borrowed(""), Cow::Borrowed(""))
+    }
+}
+
 impl<T: SessionStream> SessionStream for TlsStream<T> {
     fn is_tls(&self) -> bool {
         true
@@ -101,6 +119,114 @@ impl SessionStream for ProxiedStream<TcpStream> {
     }
 }
 
+// Fails reads and writes that make no progress within the configured timeouts,
+// so that stalled clients are disconnected. The timer restarts after every
+// read or write that completes.
+pub struct TimeoutStream<T> {
+    inner: T,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    read_deadline: Option<Pin<Box<Sleep>>>,
+    write_deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl<T> TimeoutStream<T> {
+    pub fn new(inner: T, read_timeout: Option<Duration>, write_timeout: Option<Duration>) -> Self {
+        TimeoutStream {
+            inner,
+            read_timeout,
+            write_timeout,
+            read_deadline: None,
+            write_deadline: None,
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+fn poll_deadline<R>(
+    deadline: &mut Option<Pin<Box<Sleep>>>,
+    timeout: Option<Duration>,
+    cx: &mut Context<'_>,
+) -> Poll<std::io::Result<R>> {
+    if let Some(timeout) = timeout {
+        let sleep = deadline.get_or_insert_with(|| Box::pin(tokio::time::sleep(timeout)));
+        if sleep.as_mut().poll(cx).is_ready() {
+            *deadline = None;
+            return Poll::Ready(Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "Connection timed out",
+            )));
+        }
+    }
+    Poll::Pending
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for TimeoutStream<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Pending => poll_deadline(&mut this.read_deadline, this.read_timeout, cx),
+            result => {
+                this.read_deadline = None;
+                result
+            }
+        }
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for TimeoutStream<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_write(cx, buf) {
+            Poll::Pending => poll_deadline(&mut this.write_deadline, this.write_timeout, cx),
+            result => {
+                this.write_deadline = None;
+                result
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll_flush(cx) {
+            Poll::Pending => poll_deadline(&mut this.write_deadline, this.write_timeout, cx),
+            result => {
+                this.write_deadline = None;
+                result
+            }
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+impl<T: SessionStream> SessionStream for TimeoutStream<T> {
+    fn is_tls(&self) -> bool {
+        self.inner.is_tls()
+    }
+
+    fn tls_version_and_cipher(&self) -> (Cow<'static, str>, Cow<'static, str>) {
+        self.inner.tls_version_and_cipher()
+    }
+}
+
 #[derive(Default)]
 pub struct NullIo {
     pub tx_buf: Vec<u8>,
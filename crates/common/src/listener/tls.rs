@@ -45,6 +45,7 @@ use super::{
         resolver::{build_acme_static_resolver, IsTlsAlpnChallenge},
         AcmeProvider,
     },
+    ocsp::OcspStapler,
     SessionStream, TcpAcceptor, TcpAcceptorResult,
 };
 
@@ -61,20 +62,50 @@ pub struct TlsManager {
 #[derive(Clone)]
 pub struct CertificateResolver {
     pub core: SharedCore,
+    pub sni: AHashMap<String, Arc<CertifiedKey>>,
+    pub ocsp: Option<Arc<OcspStapler>>,
 }
 
 impl CertificateResolver {
     pub fn new(core: SharedCore) -> Self {
-        Self { core }
+        Self {
+            core,
+            sni: AHashMap::new(),
+            ocsp: None,
+        }
+    }
+
+    pub fn with_sni(mut self, sni: AHashMap<String, Arc<CertifiedKey>>) -> Self {
+        self.sni = sni;
+        self
+    }
+
+    pub fn resolve_name(&self, name: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        // Listener certificates take precedence over the global ones
+        let cert = name
+            .filter(|_| !self.sni.is_empty())
+            .and_then(|name| {
+                let name = name.to_ascii_lowercase();
+                self.sni
+                    .get(&name)
+                    .or_else(|| {
+                        name.split_once('.')
+                            .and_then(|(_, domain)| self.sni.get(&format!("*.{domain}")))
+                    })
+                    .cloned()
+            })
+            .or_else(|| self.core.as_ref().load().resolve_certificate(name))?;
+
+        Some(match &self.ocsp {
+            Some(ocsp) => ocsp.staple(cert),
+            None => cert,
+        })
     }
 }
 
 impl ResolvesServerCert for CertificateResolver {
     fn resolve(&self, hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
-        self.core
-            .as_ref()
-            .load()
-            .resolve_certificate(hello.server_name())
+        self.resolve_name(hello.server_name())
     }
 }
 
@@ -31,7 +31,7 @@ use store::{
 };
 use tracing_appender::non_blocking::WorkerGuard;
 use utils::{
-    config::{Config, ConfigKey},
+    config::{read_config_file, Config, ConfigKey},
     failed, UnwrapFailure,
 };
 
@@ -57,10 +57,15 @@ const HELP: &str = r#"Stalwart Mail Server
 Usage: stalwart-mail [OPTIONS]
 
 Options:
-  -c, --config <PATH>              Start server with the specified configuration file
+  -c, --config <PATH>              Start server with the specified configuration file,
+                                   may be repeated with later files taking precedence,
+                                   a path of '-' reads it from standard input
+  -p, --profile <NAME>             Apply the keys of the specified configuration profile
   -e, --export <PATH>              Export all store data to a specific path
   -i, --import <PATH>              Import store data from a specific path
   -I, --init <PATH>                Initialize a new server at a specific path
+  -t, --validate <PATH>            Validate a configuration file and exit, merging any
+                                   '--config' files underneath it and applying '--profile'
   -h, --help                       Print help
   -V, --version                    Print version
 "#;
@@ -74,10 +79,15 @@ enum ImportExport {
 
 impl BootManager {
     pub async fn init() -> Self {
-        let mut config_path = std::env::var("CONFIG_PATH").ok();
+        let mut config_paths = std::env::var("CONFIG_PATH")
+            .ok()
+            .into_iter()
+            .collect::<Vec<_>>();
         let mut import_export = ImportExport::None;
+        let mut profile = std::env::var("CONFIG_PROFILE").ok();
+        let mut validate_path = None;
 
-        if config_path.is_none() {
+        if config_paths.is_empty() {
             let mut args = std::env::args().skip(1);
 
             while let Some(arg) = args.next().and_then(|arg| {
@@ -101,12 +111,18 @@ impl BootManager {
                         std::process::exit(0);
                     }
                     ("config" | "c", Some(value)) => {
-                        config_path = Some(value);
+                        config_paths.push(value);
+                    }
+                    ("profile" | "p", Some(value)) => {
+                        profile = Some(value);
                     }
                     ("init" | "I", Some(value)) => {
                         quickstart(value);
                         std::process::exit(0);
                     }
+                    ("validate" | "t", Some(value)) => {
+                        validate_path = Some(value);
+                    }
                     ("export" | "e", Some(value)) => {
                         import_export = ImportExport::Export(value.into());
                     }
@@ -122,7 +138,25 @@ impl BootManager {
                 }
             }
 
-            if config_path.is_none() {
+            // Validate using the same base files and profile as a server start
+            if let Some(path) = validate_path {
+                let base_paths = config_paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+                match Config::validate(&path, &base_paths, profile.as_deref()).await {
+                    Ok(warnings) => {
+                        for warning in warnings {
+                            eprintln!("{warning}");
+                        }
+                        eprintln!("Configuration file {path:?} is valid.");
+                        std::process::exit(0);
+                    }
+                    Err(err) => {
+                        eprintln!("ERROR: {err}");
+                        std::process::exit(1);
+                    }
+                }
+            }
+
+            if config_paths.is_empty() {
                 if import_export == ImportExport::None {
                     eprintln!("{HELP}");
                 } else {
@@ -133,9 +167,15 @@ impl BootManager {
         }
 
         // Read main configuration file
-        let cfg_local_path = PathBuf::from(config_path.unwrap());
+        // When multiple files are specified, the last one is the local configuration
+        // file and the preceding ones are merged underneath it
+        let cfg_local_path = PathBuf::from(config_paths.pop().unwrap());
+        let cfg_base_paths = config_paths
+            .into_iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
         let mut config = Config::default();
-        match std::fs::read_to_string(&cfg_local_path) {
+        match read_config_file(&cfg_local_path) {
             Ok(value) => {
                 config.parse(&value).failed("Invalid configuration file");
             }
@@ -145,17 +185,20 @@ impl BootManager {
         }
         let cfg_local = config.keys.clone();
 
-        // Resolve environment macros
-        config.resolve_macros(&["env"]).await;
+        // Resolve environment macros, including those in included files
+        config
+            .resolve_local(&cfg_local_path, &cfg_base_paths, profile.as_deref())
+            .await;
 
         // Parser servers
         let mut servers = Servers::parse(&mut config);
 
         // Bind ports and drop privileges
-        servers.bind_and_drop_priv(&mut config);
+        servers.bind_and_drop_priv(&mut config).await;
 
         // Resolve file and configuration macros
         config.resolve_macros(&["file", "cfg"]).await;
+        config.unescape_macros();
 
         // Load stores
         let mut stores = Stores::parse(&mut config).await;
@@ -170,6 +213,8 @@ impl BootManager {
                 .and_then(|id| stores.stores.get(id))
                 .cloned()
                 .unwrap_or_default(),
+            cfg_profile: profile,
+            cfg_base_paths,
         };
 
         // Extend configuration with settings stored in the db
@@ -387,7 +432,7 @@ fn quickstart(path: impl Into<PathBuf>) {
             .collect::<String>()
     });
 
-    std::fs::write(
+    Config::write_atomic(
         path.join("etc").join("config.toml"),
         QUICKSTART_CONFIG
             .replace("_P_", &path.to_string_lossy())
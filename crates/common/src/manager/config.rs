@@ -34,7 +34,7 @@ use store::{
     Deserialize, IterateParams, Store, ValueKey,
 };
 use utils::{
-    config::{Config, ConfigKey},
+    config::{is_stdin_path, Config, ConfigKey},
     glob::GlobPattern,
 };
 
@@ -44,6 +44,8 @@ pub struct ConfigManager {
     pub cfg_local_path: PathBuf,
     pub cfg_local_patterns: Arc<Patterns>,
     pub cfg_store: Store,
+    pub cfg_profile: Option<String>,
+    pub cfg_base_paths: Vec<PathBuf>,
 }
 
 #[derive(Default)]
@@ -76,12 +78,63 @@ impl ConfigManager {
             keys: self.cfg_local.load().as_ref().clone(),
             ..Default::default()
         };
-        config.resolve_all_macros().await;
+        self.resolve_local(&mut config).await;
         self.extend_config(&mut config, prefix)
             .await
             .map(|_| config)
     }
 
+    // Resolves the local keys in the same way as at boot, using the base files
+    // and profile the server was started with
+    async fn resolve_local(&self, config: &mut Config) {
+        config
+            .resolve_local(
+                &self.cfg_local_path,
+                &self.cfg_base_paths,
+                self.cfg_profile.as_deref(),
+            )
+            .await;
+        config.resolve_all_macros().await;
+    }
+
+    // Re-reads the local configuration file and returns the keys that changed.
+    // The file is validated first so a broken edit never replaces the
+    // running settings.
+    pub async fn reload_local(&self) -> utils::config::Result<Vec<String>> {
+        // A configuration read from standard input cannot be read again
+        if is_stdin_path(&self.cfg_local_path) {
+            return Ok(vec![]);
+        }
+
+        let new_config = std::fs::read_to_string(&self.cfg_local_path)
+            .map_err(|err| {
+                format!(
+                    "Could not read configuration file {}: {err}",
+                    self.cfg_local_path.display()
+                )
+            })
+            .and_then(Config::new)?;
+        let mut resolved = Config {
+            keys: new_config.keys.clone(),
+            ..Default::default()
+        };
+        self.resolve_local(&mut resolved).await;
+        if let Some((key, err)) = resolved.errors.iter().min_by_key(|(key, _)| *key) {
+            return Err(err.describe(key));
+        }
+        let changed_keys = Config {
+            keys: self.cfg_local.load().as_ref().clone(),
+            ..Default::default()
+        }
+        .changed_keys(&new_config);
+
+        if !changed_keys.is_empty() {
+            self.cfg_local.store(new_config.keys.into());
+        }
+
+        Ok(changed_keys)
+    }
+
     pub(crate) async fn extend_config(
         &self,
         config: &mut Config,
@@ -333,13 +386,14 @@ impl ConfigManager {
 
         self.cfg_local.store(map.into());
 
-        tokio::fs::write(&self.cfg_local_path, cfg_text)
-            .await
-            .map_err(|err| {
-                store::Error::InternalError(format!(
-                    "Failed to write local configuration file: {err}"
-                ))
-            })
+        // Changes to a configuration read from standard input are kept in memory
+        if is_stdin_path(&self.cfg_local_path) {
+            return Ok(());
+        }
+
+        Config::write_atomic(&self.cfg_local_path, cfg_text).map_err(|err| {
+            store::Error::InternalError(format!("Failed to write local configuration file: {err}"))
+        })
     }
 
     pub async fn update_config_resource(&self, resource_id: &str) -> store::Result<Option<String>> {
@@ -563,6 +617,8 @@ impl Clone for ConfigManager {
             cfg_local_path: self.cfg_local_path.clone(),
             cfg_local_patterns: self.cfg_local_patterns.clone(),
             cfg_store: self.cfg_store.clone(),
+            cfg_profile: self.cfg_profile.clone(),
+            cfg_base_paths: self.cfg_base_paths.clone(),
         }
     }
 }
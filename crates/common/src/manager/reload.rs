@@ -21,7 +21,9 @@
  * for more details.
 */
 
-use ahash::AHashSet;
+use std::{path::PathBuf, time::Duration};
+
+use ahash::{AHashMap, AHashSet};
 use arc_swap::ArcSwap;
 use parking_lot::RwLock;
 use store::Stores;
@@ -33,7 +35,7 @@ use crate::{
         tracers::Tracers,
     },
     listener::blocked::BLOCKED_IP_KEY,
-    Core,
+    Core, SharedCore,
 };
 
 use super::config::{ConfigManager, Patterns};
@@ -80,6 +82,24 @@ impl Core {
         Ok(config.into())
     }
 
+    // Returns the files referenced by file macros in the certificate settings
+    pub async fn certificate_files(&self) -> store::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for (_, value) in self.storage.config.list("certificate.", false).await? {
+            let mut value = value.as_str();
+            while let Some((_, rest)) = value.split_once("%{file:") {
+                if let Some((path, rest)) = rest.split_once("}%") {
+                    files.push(PathBuf::from(path.trim()));
+                    value = rest;
+                } else {
+                    break;
+                }
+            }
+        }
+
+        Ok(files)
+    }
+
     pub async fn reload_lookups(&self) -> store::Result<ReloadResult> {
         let mut config = self.storage.config.build_config("certificate").await?;
         let mut stores = Stores::default();
@@ -126,6 +146,8 @@ impl Core {
                 .and_then(|id| stores.stores.get(id))
                 .cloned()
                 .unwrap_or_default(),
+            cfg_profile: self.storage.config.cfg_profile.clone(),
+            cfg_base_paths: self.storage.config.cfg_base_paths.clone(),
         };
 
         // Parse settings and build shared core
@@ -162,6 +184,71 @@ impl Core {
     }
 }
 
+pub fn spawn_certificate_watcher(core: SharedCore, interval: Duration) {
+    tokio::spawn(async move {
+        let mut modified = AHashMap::new();
+        let mut is_first_run = true;
+
+        loop {
+            let core_ = core.load().clone();
+            let files = match core_.certificate_files().await {
+                Ok(files) => files,
+                Err(err) => {
+                    tracing::error!(
+                        context = "tls",
+                        event = "error",
+                        reason = %err,
+                        "Failed to obtain certificate files."
+                    );
+                    Vec::new()
+                }
+            };
+
+            // Look for certificate or key files that have been modified
+            let mut has_changes = false;
+            for file in files {
+                let mtime = tokio::fs::metadata(&file)
+                    .await
+                    .and_then(|metadata| metadata.modified())
+                    .ok();
+                if modified.insert(file, mtime) != Some(mtime) {
+                    has_changes = true;
+                }
+            }
+
+            if has_changes && !is_first_run {
+                match core_.reload_certificates().await {
+                    Ok(result) if result.config.errors.is_empty() => {
+                        tracing::info!(
+                            context = "tls",
+                            event = "reload",
+                            "Reloaded certificates after a file change."
+                        );
+                    }
+                    Ok(result) => {
+                        // Certificates that failed to load keep their previous version,
+                        // try again on the next run in case the files were still being written
+                        result.config.log_errors(false);
+                        modified.clear();
+                    }
+                    Err(err) => {
+                        tracing::error!(
+                            context = "tls",
+                            event = "error",
+                            reason = %err,
+                            "Failed to reload certificates."
+                        );
+                        modified.clear();
+                    }
+                }
+            }
+
+            is_first_run = false;
+            tokio::time::sleep(interval).await;
+        }
+    });
+}
+
 impl From<Config> for ReloadResult {
     fn from(config: Config) -> Self {
         Self {
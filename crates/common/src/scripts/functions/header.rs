@@ -111,3 +111,15 @@ pub fn fn_is_header_utf8_valid<'x>(ctx: &'x Context<'x>, v: Vec<Variable>) -> Va
         })
         .unwrap_or(Variable::Integer(1))
 }
+
+pub fn fn_is_header_name<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variable {
+    is_header_name(v[0].to_string().as_ref()).into()
+}
+
+// Field names are made of printable ASCII characters other than the colon (RFC 5322 ftext)
+pub fn is_header_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| (0x21..=0x7e).contains(&b) && b != b':')
+}
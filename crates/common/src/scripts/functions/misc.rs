@@ -26,7 +26,7 @@ use std::net::IpAddr;
 use mail_auth::common::resolver::ToReverseName;
 use sha1::Sha1;
 use sha2::{Sha256, Sha512};
-use sieve::{runtime::Variable, Context};
+use sieve::{compiler::Number, runtime::Variable, Context};
 
 use super::ApplyString;
 
@@ -86,6 +86,15 @@ pub fn fn_detect_file_type<'x>(ctx: &'x Context<'x>, v: Vec<Variable>) -> Variab
         .unwrap_or_default()
 }
 
+pub fn fn_to_fixed<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variable {
+    let value = match v[0].to_number_checked() {
+        Some(Number::Integer(n)) => n as f64,
+        Some(Number::Float(n)) => n,
+        None => return v[0].clone(),
+    };
+    format!("{:.*}", v[1].to_usize(), value).into()
+}
+
 pub fn fn_hash<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variable {
     use sha1::Digest;
     let hash = v[1].to_string();
@@ -23,7 +23,7 @@
 
 mod array;
 mod email;
-mod header;
+pub mod header;
 pub mod html;
 mod image;
 mod misc;
@@ -60,9 +60,11 @@ pub fn register_functions() -> FunctionMap {
         .with_function("count_uppercase", fn_count_uppercase)
         .with_function("count_lowercase", fn_count_lowercase)
         .with_function("count_chars", fn_count_chars)
+        .with_function("printable_ascii", fn_printable_ascii)
         .with_function("dedup", fn_dedup)
         .with_function("lines", fn_lines)
         .with_function("is_header_utf8_valid", fn_is_header_utf8_valid)
+        .with_function("is_header_name", fn_is_header_name)
         .with_function("img_metadata", fn_img_metadata)
         .with_function("is_ip_addr", fn_is_ip_addr)
         .with_function("is_ipv4_addr", fn_is_ipv4_addr)
@@ -101,6 +103,7 @@ pub fn register_functions() -> FunctionMap {
         .with_function_args("strip_suffix", fn_strip_suffix, 2)
         .with_function_args("is_intersect", fn_is_intersect, 2)
         .with_function_args("hash", fn_hash, 2)
+        .with_function_args("to_fixed", fn_to_fixed, 2)
         .with_function_no_args("is_encoding_problem", fn_is_encoding_problem)
         .with_function_no_args("is_attachment", fn_is_attachment)
         .with_function_no_args("is_body", fn_is_body)
@@ -116,6 +116,14 @@ pub fn fn_count_chars<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variable {
     v[0].to_string().as_ref().chars().count().into()
 }
 
+pub fn fn_printable_ascii<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variable {
+    v[0].to_string()
+        .chars()
+        .filter(|c| c.is_ascii() && !c.is_ascii_control())
+        .collect::<String>()
+        .into()
+}
+
 pub fn fn_eq_ignore_case<'x>(_: &'x Context<'x>, v: Vec<Variable>) -> Variable {
     v[0].to_string()
         .eq_ignore_ascii_case(v[1].to_string().as_ref())
@@ -38,6 +38,14 @@ pub fn register_train(plugin_id: u32, fnc_map: &mut FunctionMap) {
     fnc_map.set_external_function("bayes_train", plugin_id, 3);
 }
 
+pub fn register_autolearn(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("bayes_autolearn", plugin_id, 3);
+}
+
+pub fn register_train_forced(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("bayes_train_forced", plugin_id, 4);
+}
+
 pub fn register_untrain(plugin_id: u32, fnc_map: &mut FunctionMap) {
     fnc_map.set_external_function("bayes_untrain", plugin_id, 3);
 }
@@ -50,15 +58,32 @@ pub fn register_is_balanced(plugin_id: u32, fnc_map: &mut FunctionMap) {
     fnc_map.set_external_function("bayes_is_balanced", plugin_id, 3);
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Training {
+    Manual,
+    // Score based training, subject to the auto-learn rate limit
+    Auto,
+    // Ground truth such as spam trap hits, learned with the given weight
+    Forced,
+}
+
 pub fn exec_train(ctx: PluginContext<'_>) -> Variable {
-    train(ctx, true)
+    train(ctx, true, Training::Manual)
+}
+
+pub fn exec_autolearn(ctx: PluginContext<'_>) -> Variable {
+    train(ctx, true, Training::Auto)
+}
+
+pub fn exec_train_forced(ctx: PluginContext<'_>) -> Variable {
+    train(ctx, true, Training::Forced)
 }
 
 pub fn exec_untrain(ctx: PluginContext<'_>) -> Variable {
-    train(ctx, false)
+    train(ctx, false, Training::Manual)
 }
 
-fn train(ctx: PluginContext<'_>, is_train: bool) -> Variable {
+fn train(ctx: PluginContext<'_>, is_train: bool, training: Training) -> Variable {
     let span: &tracing::Span = ctx.span;
     let store = match &ctx.arguments[0] {
         Variable::String(v) if !v.is_empty() => ctx.core.storage.lookups.get(v.as_ref()),
@@ -82,8 +107,40 @@ fn train(ctx: PluginContext<'_>, is_train: bool) -> Variable {
     if text.is_empty() {
         return false.into();
     }
+    let weight = if training == Training::Forced {
+        ctx.arguments[3].to_integer().clamp(1, u32::MAX as i64) as u32
+    } else {
+        1
+    };
     let handle = ctx.handle;
 
+    // Limit the number of automatic training operations per period
+    if training == Training::Auto {
+        if let Some(rate) = &ctx.core.sieve.bayes_learn_rate {
+            match handle.block_on(store.is_rate_allowed(b"bayes:learn", rate, false)) {
+                Ok(None) => {}
+                Ok(Some(_)) => {
+                    tracing::debug!(
+                        parent: span,
+                        context = "sieve:bayes_train",
+                        event = "rate-limited",
+                        is_spam = is_spam,
+                    );
+                    return false.into();
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        parent: span,
+                        context = "sieve:bayes_train",
+                        event = "failed",
+                        reason = %err,
+                    );
+                    return false.into();
+                }
+            }
+        }
+    }
+
     // Train the model
     let mut model = BayesModel::default();
     model.train(
@@ -102,6 +159,7 @@ fn train(ctx: PluginContext<'_>, is_train: bool) -> Variable {
         context = "sieve:bayes_train",
         event = "train",
         is_spam = is_spam,
+        weight = weight,
         num_tokens = model.weights.len(),
     );
 
@@ -116,7 +174,11 @@ fn train(ctx: PluginContext<'_>, is_train: bool) -> Variable {
                             .write(hash.h1)
                             .write(hash.h2)
                             .finalize(),
-                        weights.into(),
+                        Weights {
+                            spam: weights.spam.saturating_mul(weight),
+                            ham: weights.ham.saturating_mul(weight),
+                        }
+                        .into(),
                         None,
                         false,
                     ),
@@ -130,9 +192,15 @@ fn train(ctx: PluginContext<'_>, is_train: bool) -> Variable {
 
         // Update training counts
         let weights = if is_spam {
-            Weights { spam: 1, ham: 0 }
+            Weights {
+                spam: weight,
+                ham: 0,
+            }
         } else {
-            Weights { spam: 0, ham: 1 }
+            Weights {
+                spam: 0,
+                ham: weight,
+            }
         };
         if handle
             .block_on(
@@ -32,12 +32,18 @@ pub fn register(plugin_id: u32, fnc_map: &mut FunctionMap) {
 }
 
 pub fn exec(ctx: PluginContext<'_>) -> Variable {
-    if let (Variable::String(name), Variable::String(value)) =
-        (&ctx.arguments[0], &ctx.arguments[1])
-    {
+    // Numeric values such as scores are written using their string form
+    let value = match &ctx.arguments[1] {
+        Variable::String(value) => value.clone(),
+        value @ (Variable::Integer(_) | Variable::Float(_)) => {
+            value.to_string().into_owned().into()
+        }
+        Variable::Array(_) => return false.into(),
+    };
+    if let Variable::String(name) = &ctx.arguments[0] {
         ctx.modifications.push(ScriptModification::AddHeader {
             name: name.clone(),
-            value: value.clone(),
+            value,
         });
         true
     } else {
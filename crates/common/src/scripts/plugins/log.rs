@@ -0,0 +1,44 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use sieve::{runtime::Variable, FunctionMap};
+
+use super::PluginContext;
+
+pub fn register_score(plugin_id: u32, fnc_map: &mut FunctionMap) {
+    fnc_map.set_external_function("log_score", plugin_id, 3);
+}
+
+pub fn exec_score(ctx: PluginContext<'_>) -> Variable {
+    tracing::debug!(
+        parent: ctx.span,
+        context = "sieve:log_score",
+        event = "score",
+        message_id = ctx.message.message_id().unwrap_or_default(),
+        tag = ctx.arguments[0].to_string().as_ref(),
+        score = ctx.arguments[1].to_string().as_ref(),
+        total = ctx.arguments[2].to_string().as_ref(),
+    );
+
+    true.into()
+}
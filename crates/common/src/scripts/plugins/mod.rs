@@ -26,6 +26,7 @@ pub mod dns;
 pub mod exec;
 pub mod headers;
 pub mod http;
+pub mod log;
 pub mod lookup;
 pub mod pyzor;
 pub mod query;
@@ -51,7 +52,7 @@ pub struct PluginContext<'x> {
     pub arguments: Vec<Variable>,
 }
 
-const PLUGINS_EXEC: [ExecPluginFnc; 18] = [
+const PLUGINS_EXEC: [ExecPluginFnc; 21] = [
     query::exec,
     exec::exec,
     lookup::exec,
@@ -66,12 +67,15 @@ const PLUGINS_EXEC: [ExecPluginFnc; 18] = [
     bayes::exec_untrain,
     bayes::exec_classify,
     bayes::exec_is_balanced,
+    bayes::exec_train_forced,
+    bayes::exec_autolearn,
     pyzor::exec,
     headers::exec,
+    log::exec_score,
     text::exec_tokenize,
     text::exec_domain_part,
 ];
-const PLUGINS_REGISTER: [RegisterPluginFnc; 18] = [
+const PLUGINS_REGISTER: [RegisterPluginFnc; 21] = [
     query::register,
     exec::register,
     lookup::register,
@@ -86,8 +90,11 @@ const PLUGINS_REGISTER: [RegisterPluginFnc; 18] = [
     bayes::register_untrain,
     bayes::register_classify,
     bayes::register_is_balanced,
+    bayes::register_train_forced,
+    bayes::register_autolearn,
     pyzor::register,
     headers::register,
+    log::register_score,
     text::register_tokenize,
     text::register_domain_part,
 ];
@@ -97,7 +97,11 @@ impl<T: SessionStream> Session<T> {
                         }
                     }
                 },
-                _ = shutdown_rx.changed() => {
+                result = shutdown_rx.changed() => {
+                    // Let the session finish while listeners are draining
+                    if result.is_ok() && !*shutdown_rx.borrow() {
+                        continue;
+                    }
                     self.write_bytes(&b"* BYE Server shutting down.\r\n"[..]).await.ok();
                     tracing::debug!(parent: &self.span, event = "shutdown", "IMAP server shutting down.");
                     break;
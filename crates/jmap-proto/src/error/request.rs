@@ -143,6 +143,14 @@ impl RequestError {
         )
     }
 
+    pub fn payload_too_large() -> Self {
+        RequestError::blank(
+            413,
+            "Payload Too Large",
+            "The request body is larger than the server is willing to process.",
+        )
+    }
+
     pub fn limit(limit_type: RequestLimitError) -> Self {
         RequestError {
             p_type: RequestErrorType::Limit,
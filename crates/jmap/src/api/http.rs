@@ -21,7 +21,13 @@
  * for more details.
 */
 
-use std::{net::IpAddr, sync::Arc};
+use std::{
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 use common::{
     expr::{functions::ResolveVariable, *},
@@ -54,6 +60,12 @@ use crate::{
 
 use super::{HtmlResponse, HttpRequest, HttpResponse, JmapSessionManager, JsonResponse};
 
+// Listener request size limit, also enforced while reading chunked bodies
+struct RequestSizeLimit {
+    max_size: u64,
+    exceeded: AtomicBool,
+}
+
 pub struct HttpSessionData {
     pub instance: Arc<ServerInstance>,
     pub local_ip: IpAddr,
@@ -363,12 +375,13 @@ impl JmapInstance {
         let span = session.span;
         let _in_flight = session.in_flight;
         let is_tls = session.stream.is_tls();
+        let mut shutdown_rx = session.instance.shutdown_rx.clone();
 
-        if let Err(http_err) = http1::Builder::new()
+        let conn = http1::Builder::new()
             .keep_alive(true)
             .serve_connection(
                 TokioIo::new(session.stream),
-                service_fn(|req: hyper::Request<body::Incoming>| {
+                service_fn(|mut req: hyper::Request<body::Incoming>| {
                     let jmap_instance = self.clone();
                     let span = span.clone();
                     let instance = session.instance.clone();
@@ -379,6 +392,30 @@ impl JmapInstance {
                             event = "request",
                             uri = req.uri().to_string(),
                         );
+
+                        // Reject oversized bodies before they are buffered
+                        let size_limit = if let Some(max_request_size) = instance.max_request_size {
+                            if req
+                                .headers()
+                                .get(header::CONTENT_LENGTH)
+                                .and_then(|h| h.to_str().ok())
+                                .and_then(|h| h.parse::<u64>().ok())
+                                .is_some_and(|size| size > max_request_size)
+                            {
+                                return Ok(request_too_large(&span, max_request_size));
+                            }
+
+                            // Bodies without a length are checked as they are read
+                            let size_limit = Arc::new(RequestSizeLimit {
+                                max_size: max_request_size,
+                                exceeded: AtomicBool::new(false),
+                            });
+                            req.extensions_mut().insert(size_limit.clone());
+                            Some(size_limit)
+                        } else {
+                            None
+                        };
+
                         let jmap = JMAP::from(jmap_instance);
 
                         // Obtain remote IP
@@ -413,6 +450,11 @@ impl JmapInstance {
                                 },
                             )
                             .await;
+                        if let Some(size_limit) = size_limit
+                            .filter(|size_limit| size_limit.exceeded.load(Ordering::Relaxed))
+                        {
+                            response = request_too_large(&span, size_limit.max_size);
+                        }
 
                         // Add custom headers
                         if !jmap.core.jmap.http_headers.is_empty() {
@@ -427,9 +469,23 @@ impl JmapInstance {
                     }
                 }),
             )
-            .with_upgrades()
-            .await
-        {
+            .with_upgrades();
+        tokio::pin!(conn);
+
+        // Finish the in-flight request when draining, drop the connection when closing
+        let result = loop {
+            tokio::select! {
+                result = conn.as_mut() => break result,
+                changed = shutdown_rx.changed() => {
+                    if changed.is_err() || *shutdown_rx.borrow() {
+                        return;
+                    }
+                    conn.as_mut().graceful_shutdown();
+                }
+            }
+        };
+
+        if let Err(http_err) = result {
             tracing::debug!(
                 parent: &span,
                 event = "error",
@@ -491,11 +547,37 @@ impl HttpSessionData {
     }
 }
 
+fn request_too_large(span: &tracing::Span, max_request_size: u64) -> HttpResponse {
+    tracing::debug!(
+        parent: span,
+        event = "reject",
+        context = "http",
+        reason = "request-too-large",
+        max_request_size = max_request_size,
+    );
+
+    // The unread body is discarded, so the connection can't be reused
+    let mut response = RequestError::payload_too_large().into_http_response();
+    response.headers_mut().insert(
+        header::CONNECTION,
+        header::HeaderValue::from_static("close"),
+    );
+    response
+}
+
 pub async fn fetch_body(req: &mut HttpRequest, max_size: usize) -> Option<Vec<u8>> {
+    let size_limit = req.extensions().get::<Arc<RequestSizeLimit>>().cloned();
     let mut bytes = Vec::with_capacity(1024);
     while let Some(Ok(frame)) = req.frame().await {
         if let Some(data) = frame.data_ref() {
-            if bytes.len() + data.len() <= max_size || max_size == 0 {
+            let size = bytes.len() + data.len();
+            if let Some(size_limit) = size_limit
+                .as_ref()
+                .filter(|size_limit| size as u64 > size_limit.max_size)
+            {
+                size_limit.exceeded.store(true, Ordering::Relaxed);
+                return None;
+            } else if size <= max_size || max_size == 0 {
                 bytes.extend_from_slice(data);
             } else {
                 return None;
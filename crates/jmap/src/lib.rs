@@ -45,6 +45,7 @@ use services::{
     delivery::spawn_delivery_manager,
     housekeeper::{self, init_housekeeper, spawn_housekeeper},
     state::{self, init_state_manager, spawn_state_manager},
+    watcher::spawn_config_watcher,
 };
 
 use smtp::core::SMTP;
@@ -179,6 +180,14 @@ impl JMAP {
         // Spawn housekeeper
         spawn_housekeeper(jmap_instance.clone(), housekeeper_rx);
 
+        // Spawn configuration watcher
+        if let Some(interval) = config
+            .property_or_default::<Option<Duration>>("config.watch.interval", "false")
+            .unwrap_or_default()
+        {
+            spawn_config_watcher(jmap_instance.clone(), interval);
+        }
+
         jmap_instance
     }
 
@@ -27,5 +27,6 @@ pub mod housekeeper;
 pub mod index;
 pub mod ingest;
 pub mod state;
+pub mod watcher;
 
 pub const IPC_CHANNEL_BUFFER: usize = 1024;
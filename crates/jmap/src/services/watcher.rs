@@ -0,0 +1,107 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::{Duration, SystemTime};
+
+use crate::JmapInstance;
+
+use super::housekeeper::Event;
+
+pub fn spawn_config_watcher(core: JmapInstance, interval: Duration) {
+    tokio::spawn(async move {
+        tracing::debug!("Configuration watcher task started.");
+
+        let path = core.core.load().storage.config.cfg_local_path.clone();
+        let mut last_modified = modified_time(&path);
+
+        loop {
+            tokio::time::sleep(interval).await;
+
+            let modified = modified_time(&path);
+            if modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+
+            let core_ = core.core.load().clone();
+            let changed_keys = match core_.storage.config.reload_local().await {
+                Ok(changed_keys) if !changed_keys.is_empty() => changed_keys,
+                Ok(_) => continue,
+                Err(err) => {
+                    tracing::warn!(
+                        context = "config",
+                        event = "error",
+                        path = %path.display(),
+                        reason = %err,
+                        "Failed to reload configuration file, keeping current settings."
+                    );
+                    continue;
+                }
+            };
+
+            match core_.reload().await {
+                Ok(result) => {
+                    if let Some(new_core) = result.new_core {
+                        tracing::info!(
+                            context = "config",
+                            event = "reload",
+                            path = %path.display(),
+                            changed_keys = ?changed_keys,
+                            "Configuration file changed, settings reloaded."
+                        );
+
+                        // Update core
+                        core.core.store(new_core.into());
+
+                        // Increment version counter
+                        core.jmap_inner.increment_config_version();
+
+                        // Reload ACME
+                        if let Err(err) =
+                            core.jmap_inner.housekeeper_tx.send(Event::AcmeReload).await
+                        {
+                            tracing::warn!(
+                                "Failed to send ACME reload event to housekeeper: {}",
+                                err
+                            );
+                        }
+                    } else {
+                        result.config.log_errors(false);
+                    }
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        context = "config",
+                        event = "error",
+                        reason = ?err,
+                        "Failed to reload configuration."
+                    );
+                }
+            }
+        }
+    });
+}
+
+fn modified_time(path: &std::path::Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -23,7 +23,10 @@
 
 use std::time::Duration;
 
-use common::{config::server::ServerProtocol, manager::boot::BootManager};
+use common::{
+    config::server::ServerProtocol,
+    manager::{boot::BootManager, reload::spawn_certificate_watcher},
+};
 use imap::core::{ImapSessionManager, IMAP};
 use jmap::{
     api::JmapSessionManager,
@@ -45,7 +48,7 @@ static GLOBAL: Jemalloc = Jemalloc;
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
     // Load config and apply macros
-    let init = BootManager::init().await;
+    let mut init = BootManager::init().await;
 
     // Parse core
     let mut config = init.config;
@@ -57,13 +60,19 @@ async fn main() -> std::io::Result<()> {
     let jmap = JMAP::init(&mut config, delivery_rx, core.clone(), smtp.inner.clone()).await;
     let imap = IMAP::init(&mut config, jmap.clone()).await;
     let gossiper = GossiperBuilder::try_parse(&mut config);
+    let drain_timeout = config
+        .property::<Duration>("shutdown.drain-timeout")
+        .unwrap_or_default();
+    let cert_watch_interval = config
+        .property_or_default::<Option<Duration>>("server.tls.watch-interval", "1m")
+        .unwrap_or_default();
 
     // Log configuration errors
     config.log_errors(init.guards.is_none());
     config.log_warnings(init.guards.is_none());
 
     // Spawn servers
-    let (shutdown_tx, shutdown_rx) = init.servers.spawn(|server, acceptor, shutdown_rx| {
+    let shutdown_rx = init.servers.spawn(|server, acceptor, shutdown_rx| {
         match &server.protocol {
             ServerProtocol::Smtp | ServerProtocol::Lmtp => server.spawn(
                 SmtpSessionManager::new(smtp.clone()),
@@ -89,9 +98,22 @@ async fn main() -> std::io::Result<()> {
                 acceptor,
                 shutdown_rx,
             ),
+            ServerProtocol::Pop3 => {
+                tracing::error!(
+                    event = "error",
+                    instance = server.id,
+                    protocol = ?server.protocol,
+                    "POP3 is not supported by this build, listener will not be started"
+                );
+            }
         };
     });
 
+    // Reload certificates when their files change
+    if let Some(interval) = cert_watch_interval {
+        spawn_certificate_watcher(core.clone(), interval);
+    }
+
     // Spawn gossip
     if let Some(gossiper) = gossiper {
         gossiper.spawn(jmap, shutdown_rx).await;
@@ -104,8 +126,8 @@ async fn main() -> std::io::Result<()> {
     ))
     .await;
 
-    // Stop services
-    let _ = shutdown_tx.send(true);
+    // Stop services, allowing in-flight sessions to finish
+    init.servers.shutdown(drain_timeout).await;
 
     // Wait for services to finish
     tokio::time::sleep(Duration::from_secs(1)).await;
@@ -126,7 +126,11 @@ impl<T: SessionStream> Session<T> {
                             }
                         }
                 },
-                _ = shutdown_rx.changed() => {
+                result = shutdown_rx.changed() => {
+                    // Let the session finish while listeners are draining
+                    if result.is_ok() && !*shutdown_rx.borrow() {
+                        continue;
+                    }
                     tracing::debug!(
                         parent: &self.span,
                         event = "disconnect",
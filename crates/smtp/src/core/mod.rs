@@ -288,8 +288,12 @@ static ref SIEVE: Arc<ServerInstance> = Arc::new(ServerInstance {
     protocol: common::config::server::ServerProtocol::Lmtp,
     acceptor: common::listener::TcpAcceptor::Plain,
     limiter: ConcurrencyLimiter::new(0),
+    ip_limiter: None,
     shutdown_rx: tokio::sync::watch::channel(false).1,
     proxy_networks: vec![],
+    allowed_networks: vec![],
+    denied_networks: vec![],
+    max_request_size: None,
 });
 }
 
@@ -29,7 +29,7 @@ use crate::{LookupStore, Stores, Value};
 #[derive(Debug, Default)]
 pub struct MemoryStore {
     entries: AHashMap<String, Value<'static>>,
-    globs: Vec<(GlobPattern, Value<'static>)>,
+    globs: Vec<(GlobPattern, usize, Value<'static>)>,
 }
 
 impl MemoryStore {
@@ -37,7 +37,7 @@ impl MemoryStore {
         self.entries.get(id).or_else(|| {
             self.globs
                 .iter()
-                .find_map(|(pattern, value)| pattern.matches(id).then_some(value))
+                .find_map(|(pattern, _, value)| pattern.matches(id).then_some(value))
         })
     }
 }
@@ -56,6 +56,7 @@ impl Stores {
                 let mut last_ch = '\0';
                 let mut has_escape = false;
                 let mut is_glob = false;
+                let mut literals = 0;
                 for ch in key.chars() {
                     match ch {
                         '\\' => {
@@ -64,7 +65,9 @@ impl Stores {
                         '*' | '?' if last_ch != '\\' => {
                             is_glob = true;
                         }
-                        _ => {}
+                        _ => {
+                            literals += 1;
+                        }
                     }
 
                     last_ch = ch;
@@ -119,7 +122,13 @@ impl Stores {
                     .entry(id.to_string())
                     .or_insert_with(MemoryStore::default);
                 if is_glob {
-                    store.globs.push((GlobPattern::compile(key, false), value));
+                    // Keep the most specific patterns first so that the longest match wins
+                    let pos = store
+                        .globs
+                        .partition_point(|(_, other_literals, _)| *other_literals >= literals);
+                    store
+                        .globs
+                        .insert(pos, (GlobPattern::compile(key, false), literals, value));
                 } else {
                     store.entries.insert(
                         if has_escape {
@@ -23,121 +23,312 @@
 
 use std::time::Duration;
 
-use chrono::{Datelike, Local, TimeDelta, TimeZone, Timelike};
+use chrono::{DateTime, Datelike, Local, LocalResult, NaiveDate, TimeDelta, TimeZone, Timelike};
 
 use super::utils::ParseValue;
 
+// How far ahead to look for the next occurrence, long enough to reach the next leap day
+const MAX_SEARCH_DAYS: u32 = 366 * 8;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SimpleCron {
     Day { hour: u32, minute: u32 },
     Week { day: u32, hour: u32, minute: u32 },
     Hour { minute: u32 },
+    Schedule(CronSchedule),
+}
+
+// Bitmaps of the values allowed in each field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CronSchedule {
+    pub minutes: u64,
+    pub hours: u32,
+    pub days: u32,
+    pub months: u16,
+    // Monday is 1, Sunday is 7
+    pub weekdays: u8,
+    pub any_day: bool,
+    pub any_weekday: bool,
+    // Zone the fields are read in, defaults to the zone of the reference time
+    pub timezone: Option<chrono_tz::Tz>,
 }
 
 impl SimpleCron {
     pub fn time_to_next(&self) -> Duration {
         let now = Local::now();
-        let next = match self {
-            SimpleCron::Day { hour, minute } => {
-                let next = Local
-                    .with_ymd_and_hms(now.year(), now.month(), now.day(), *hour, *minute, 0)
-                    .earliest()
-                    .unwrap_or_else(|| now - TimeDelta::try_seconds(1).unwrap_or_default());
-                if next < now {
-                    next + TimeDelta::try_days(1).unwrap_or_default()
-                } else {
-                    next
-                }
-            }
-            SimpleCron::Week { day, hour, minute } => {
-                let next = Local
-                    .with_ymd_and_hms(now.year(), now.month(), now.day(), *hour, *minute, 0)
-                    .earliest()
-                    .unwrap_or_else(|| now - TimeDelta::try_seconds(1).unwrap_or_default());
-                if next < now {
-                    next + TimeDelta::try_days(
-                        (7 - now.weekday().number_from_monday() + *day).into(),
-                    )
-                    .unwrap_or_default()
-                } else {
-                    next
-                }
-            }
-            SimpleCron::Hour { minute } => {
-                let next = Local
-                    .with_ymd_and_hms(now.year(), now.month(), now.day(), now.hour(), *minute, 0)
-                    .earliest()
-                    .unwrap_or_else(|| now - TimeDelta::try_seconds(1).unwrap_or_default());
-                if next < now {
-                    next + TimeDelta::try_hours(1).unwrap_or_default()
-                } else {
-                    next
+        self.next_after(&now)
+            .and_then(|next| (next - now).to_std().ok())
+            .unwrap_or(Duration::from_secs(86400))
+    }
+
+    pub fn next_after<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        self.schedule().next_after(now)
+    }
+
+    pub fn schedule(&self) -> CronSchedule {
+        match *self {
+            SimpleCron::Day { hour, minute } => CronSchedule {
+                minutes: 1 << minute,
+                hours: 1 << hour,
+                ..CronSchedule::default()
+            },
+            SimpleCron::Week { day, hour, minute } => CronSchedule {
+                minutes: 1 << minute,
+                hours: 1 << hour,
+                weekdays: 1 << day,
+                any_weekday: false,
+                ..CronSchedule::default()
+            },
+            SimpleCron::Hour { minute } => CronSchedule {
+                minutes: 1 << minute,
+                ..CronSchedule::default()
+            },
+            SimpleCron::Schedule(schedule) => schedule,
+        }
+    }
+}
+
+impl CronSchedule {
+    pub fn next_after<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        match self.timezone {
+            Some(timezone) => self
+                .next_local(&now.with_timezone(&timezone))
+                .map(|next| next.with_timezone(&now.timezone())),
+            None => self.next_local(now),
+        }
+    }
+
+    // Local times skipped by a DST change never fire, and times
+    // repeated by one fire only on their first occurrence
+    fn next_local<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let timezone = now.timezone();
+        let start = now
+            .naive_local()
+            .with_second(0)?
+            .with_nanosecond(0)?
+            .checked_add_signed(TimeDelta::try_minutes(1)?)?;
+        let mut date = start.date();
+
+        for _ in 0..MAX_SEARCH_DAYS {
+            if self.matches_date(date) {
+                for hour in (0..24).filter(|hour| self.hours & (1 << hour) != 0) {
+                    for minute in (0..60).filter(|minute| self.minutes & (1 << minute) != 0) {
+                        let candidate = date.and_hms_opt(hour, minute, 0)?;
+                        if candidate < start {
+                            continue;
+                        }
+
+                        match timezone.from_local_datetime(&candidate) {
+                            LocalResult::Single(next) | LocalResult::Ambiguous(next, _)
+                                if next > *now =>
+                            {
+                                return Some(next);
+                            }
+                            _ => (),
+                        }
+                    }
                 }
             }
-        };
+            date = date.succ_opt()?;
+        }
+
+        None
+    }
 
-        (next - now).to_std().unwrap()
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        if self.months & (1 << date.month()) == 0 {
+            return false;
+        }
+
+        // Standard cron rules, when both day fields are restricted either may match
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().number_from_monday()) != 0;
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        }
+    }
+}
+
+impl Default for CronSchedule {
+    fn default() -> Self {
+        CronSchedule {
+            minutes: 1,
+            hours: (1 << 24) - 1,
+            days: !1,
+            months: ((1 << 13) - 1) & !1,
+            weekdays: !1,
+            any_day: true,
+            any_weekday: true,
+            timezone: None,
+        }
     }
 }
 
 impl ParseValue for SimpleCron {
     fn parse_value(value: &str) -> super::Result<Self> {
-        let mut hour = 0;
-        let mut minute = 0;
-
-        for (pos, value) in value.split(' ').enumerate() {
-            if pos == 0 {
-                minute = value
-                    .parse::<u32>()
-                    .map_err(|_| "Invalid cron key: failed to parse cron minute".to_string())?;
-                if !(0..=59).contains(&minute) {
-                    return Err(format!(
-                        "Invalid cron key: failed to parse minute, invalid value: {minute}"
-                    ));
-                }
-            } else if pos == 1 {
-                if value
-                    .as_bytes()
-                    .first()
-                    .ok_or_else(|| "Invalid cron key: failed to parse cron hour".to_string())?
-                    == &b'*'
-                {
-                    return Ok(SimpleCron::Hour { minute });
-                } else {
-                    hour = value
-                        .parse::<u32>()
-                        .map_err(|_| "Invalid cron key: failed to parse cron hour".to_string())?;
-                    if !(0..=23).contains(&hour) {
-                        return Err(format!(
-                            "Invalid cron key: failed to parse hour, invalid value: {hour}"
-                        ));
+        // An IANA zone may precede the expression, as in "TZ=Europe/Paris 0 3 * * *"
+        if let Some(value) = value.trim().strip_prefix("TZ=") {
+            let (zone, value) = value.split_once(char::is_whitespace).unwrap_or((value, ""));
+            let timezone = zone
+                .parse::<chrono_tz::Tz>()
+                .map_err(|_| format!("Invalid cron key: unknown timezone {zone:?}"))?;
+
+            Ok(SimpleCron::Schedule(CronSchedule {
+                timezone: Some(timezone),
+                ..parse_expression(value)?.schedule()
+            }))
+        } else {
+            parse_expression(value)
+        }
+    }
+}
+
+fn parse_expression(value: &str) -> super::Result<SimpleCron> {
+    let value = match value.trim() {
+        "@hourly" => "0 * * * *",
+        "@daily" | "@midnight" => "0 0 * * *",
+        "@weekly" => "0 0 * * 0",
+        "@monthly" => "0 0 1 * *",
+        "@yearly" | "@annually" => "0 0 1 1 *",
+        value if value.starts_with('@') => {
+            return Err(format!("Invalid cron key: unknown shortcut {value:?}"));
+        }
+        value => value,
+    };
+    let fields = value.split_whitespace().collect::<Vec<_>>();
+
+    match fields.as_slice() {
+        // Minute, hour and weekday, 1 (Monday) to 7 (Sunday)
+        [minute, hour, weekday] => {
+            let schedule = CronSchedule {
+                minutes: parse_field(minute, "minute", 0, 59)?,
+                hours: parse_field(hour, "hour", 0, 23)? as u32,
+                weekdays: parse_field(weekday, "weekday", 1, 7)? as u8,
+                any_weekday: *weekday == "*",
+                ..Default::default()
+            };
+
+            Ok(
+                match (
+                    single_value(minute),
+                    single_value(hour),
+                    single_value(weekday),
+                ) {
+                    (Some(minute), None, None) if *hour == "*" && *weekday == "*" => {
+                        SimpleCron::Hour { minute }
                     }
-                }
-            } else if pos == 2 {
-                if value
-                    .as_bytes()
-                    .first()
-                    .ok_or_else(|| "Invalid cron key: failed to parse cron weekday".to_string())?
-                    == &b'*'
-                {
-                    return Ok(SimpleCron::Day { hour, minute });
-                } else {
-                    let day = value.parse::<u32>().map_err(|_| {
-                        "Invalid cron key: failed to parse cron weekday".to_string()
-                    })?;
-                    if !(1..=7).contains(&hour) {
-                        return Err(format!(
-                            "Invalid cron key: failed to parse weekday, invalid value: {}, range is 1 (Monday) to 7 (Sunday).",
-                            hour,
-                        ));
+                    (Some(minute), Some(hour), None) if *weekday == "*" => {
+                        SimpleCron::Day { hour, minute }
                     }
+                    (Some(minute), Some(hour), Some(day)) => SimpleCron::Week { day, hour, minute },
+                    _ => SimpleCron::Schedule(schedule),
+                },
+            )
+        }
+        // Standard cron, weekdays 0 to 7 with both 0 and 7 being Sunday
+        [minute, hour, day, month, weekday] => {
+            let mut weekdays = parse_field(weekday, "weekday", 0, 7)? as u8;
+            if weekdays & 1 != 0 {
+                weekdays = (weekdays & !1) | (1 << 7);
+            }
+            let schedule = CronSchedule {
+                minutes: parse_field(minute, "minute", 0, 59)?,
+                hours: parse_field(hour, "hour", 0, 23)? as u32,
+                days: parse_field(day, "day", 1, 31)? as u32,
+                months: parse_field(month, "month", 1, 12)? as u16,
+                weekdays,
+                any_day: *day == "*",
+                any_weekday: *weekday == "*",
+                timezone: None,
+            };
 
-                    return Ok(SimpleCron::Week { day, hour, minute });
-                }
+            // Reject dates that never occur, such as February 30
+            if schedule.any_weekday
+                && !(1..=12).any(|month: u32| {
+                    schedule.months & (1 << month) != 0
+                        && (schedule.days as u64) & ((1 << (days_in_month(month) + 1)) - 1) != 0
+                })
+            {
+                return Err(format!(
+                    "Invalid cron key: expression {value:?} never matches a date"
+                ));
             }
+
+            Ok(SimpleCron::Schedule(schedule))
+        }
+        _ => Err(format!(
+            "Invalid cron key: expected 3 or 5 fields, found {}",
+            fields.len()
+        )),
+    }
+}
+
+fn parse_field(value: &str, name: &str, min: u32, max: u32) -> super::Result<u64> {
+    let mut bits = 0u64;
+
+    for item in value.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| {
+                        format!("Invalid cron key: failed to parse {name} step {step:?}")
+                    })?,
+            ),
+            None => (item, 1),
+        };
+        let (from, to) = if range == "*" {
+            (min, max)
+        } else if let Some((from, to)) = range.split_once('-') {
+            (
+                parse_number(from, name, min, max)?,
+                parse_number(to, name, min, max)?,
+            )
+        } else {
+            let from = parse_number(range, name, min, max)?;
+            (from, if step > 1 { max } else { from })
+        };
+        if from > to {
+            return Err(format!(
+                "Invalid cron key: invalid {name} range {range:?}, start is after end"
+            ));
         }
 
-        Err("Invalid cron key: parse cron expression.".to_string())
+        for value in (from..=to).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+
+    Ok(bits)
+}
+
+fn parse_number(value: &str, name: &str, min: u32, max: u32) -> super::Result<u32> {
+    let number = value
+        .parse::<u32>()
+        .map_err(|_| format!("Invalid cron key: failed to parse cron {name} {value:?}"))?;
+    if (min..=max).contains(&number) {
+        Ok(number)
+    } else {
+        Err(format!(
+            "Invalid cron key: failed to parse {name}, invalid value: {number}, range is {min} to {max}."
+        ))
+    }
+}
+
+fn single_value(value: &str) -> Option<u32> {
+    value.parse().ok()
+}
+
+fn days_in_month(month: u32) -> u32 {
+    match month {
+        2 => 29,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
     }
 }
 
@@ -146,3 +337,268 @@ impl Default for SimpleCron {
         SimpleCron::Hour { minute: 0 }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::{FixedOffset, NaiveDateTime, Utc};
+
+    use super::*;
+
+    // Central European time in 2024, switching to summer time on March 31
+    // and back on October 27, both at 01:00 UTC
+    #[derive(Debug, Clone, Copy)]
+    struct TestZone;
+
+    impl TestZone {
+        fn utc_offset(utc: &NaiveDateTime) -> i32 {
+            let spring = NaiveDate::from_ymd_opt(2024, 3, 31).unwrap();
+            let fall = NaiveDate::from_ymd_opt(2024, 10, 27).unwrap();
+            if *utc >= spring.and_hms_opt(1, 0, 0).unwrap()
+                && *utc < fall.and_hms_opt(1, 0, 0).unwrap()
+            {
+                7200
+            } else {
+                3600
+            }
+        }
+    }
+
+    impl TimeZone for TestZone {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            TestZone
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+            let offsets = [7200, 3600]
+                .into_iter()
+                .filter(|offset| {
+                    let utc = *local - TimeDelta::try_seconds(*offset as i64).unwrap();
+                    TestZone::utc_offset(&utc) == *offset
+                })
+                .map(|offset| FixedOffset::east_opt(offset).unwrap())
+                .collect::<Vec<_>>();
+            match offsets.as_slice() {
+                [offset] => LocalResult::Single(*offset),
+                [earliest, latest] => LocalResult::Ambiguous(*earliest, *latest),
+                _ => LocalResult::None,
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            self.offset_from_utc_datetime(&utc.and_hms_opt(0, 0, 0).unwrap())
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            FixedOffset::east_opt(TestZone::utc_offset(utc)).unwrap()
+        }
+    }
+
+    fn next_utc(expr: &str, now: DateTime<Utc>) -> DateTime<Utc> {
+        SimpleCron::parse_value(expr)
+            .unwrap()
+            .next_after(&now)
+            .unwrap()
+    }
+
+    #[test]
+    fn parse_cron() {
+        for (expr, expected) in [
+            ("15 * *", SimpleCron::Hour { minute: 15 }),
+            ("0 3 *", SimpleCron::Day { hour: 3, minute: 0 }),
+            (
+                "30 4 7",
+                SimpleCron::Week {
+                    day: 7,
+                    hour: 4,
+                    minute: 30,
+                },
+            ),
+            ("@daily", SimpleCron::parse_value("0 0 * * *").unwrap()),
+            ("@hourly", SimpleCron::parse_value("0 * * * *").unwrap()),
+        ] {
+            assert_eq!(SimpleCron::parse_value(expr).unwrap(), expected, "{expr}");
+        }
+
+        let schedule = SimpleCron::parse_value("*/15 9-17 1,15,30 * 1-5")
+            .unwrap()
+            .schedule();
+        assert_eq!(schedule.minutes, 1 | (1 << 15) | (1 << 30) | (1 << 45));
+        assert_eq!(schedule.hours, ((1 << 18) - 1) & !((1 << 9) - 1));
+        assert_eq!(schedule.days, (1 << 1) | (1 << 15) | (1 << 30));
+        assert_eq!(schedule.weekdays, 0b111110);
+        assert!(!schedule.any_day);
+        assert!(!schedule.any_weekday);
+
+        // Both 0 and 7 are Sunday
+        for expr in ["0 0 * * 0", "0 0 * * 7", "@weekly"] {
+            assert_eq!(
+                SimpleCron::parse_value(expr).unwrap().schedule().weekdays,
+                1 << 7,
+                "{expr}"
+            );
+        }
+
+        for expr in [
+            "",
+            "15",
+            "1 2 3 4",
+            "60 * *",
+            "0 24 *",
+            "0 0 8",
+            "a * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "0 0 32 * *",
+            "0 0 * 13 *",
+            "0 0 30 2 *",
+            "1,,2 * * * *",
+            "@fortnightly",
+        ] {
+            assert!(SimpleCron::parse_value(expr).is_err(), "{expr:?}");
+        }
+    }
+
+    #[test]
+    fn cron_next_after() {
+        let now = Utc.with_ymd_and_hms(2024, 6, 1, 10, 0, 0).unwrap();
+
+        // Steps, ranges and lists, starting on a Saturday
+        assert_eq!(
+            next_utc("*/15 9-17 * * 1-5", now),
+            Utc.with_ymd_and_hms(2024, 6, 3, 9, 0, 0).unwrap()
+        );
+        assert_eq!(
+            next_utc("10,40 * *", now),
+            Utc.with_ymd_and_hms(2024, 6, 1, 10, 10, 0).unwrap()
+        );
+        assert_eq!(
+            next_utc("0 10 *", now),
+            Utc.with_ymd_and_hms(2024, 6, 2, 10, 0, 0).unwrap()
+        );
+        assert_eq!(
+            next_utc("0 8 1", now),
+            Utc.with_ymd_and_hms(2024, 6, 3, 8, 0, 0).unwrap()
+        );
+
+        // Either day field may match when both are restricted
+        assert_eq!(
+            next_utc(
+                "0 0 13 * 5",
+                Utc.with_ymd_and_hms(2024, 9, 1, 0, 0, 0).unwrap()
+            ),
+            Utc.with_ymd_and_hms(2024, 9, 6, 0, 0, 0).unwrap()
+        );
+
+        // Leap days
+        assert_eq!(
+            next_utc(
+                "0 0 29 2 *",
+                Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap()
+            ),
+            Utc.with_ymd_and_hms(2028, 2, 29, 0, 0, 0).unwrap()
+        );
+        assert_eq!(
+            next_utc(
+                "0 12 * * *",
+                Utc.with_ymd_and_hms(2028, 2, 28, 12, 0, 0).unwrap()
+            ),
+            Utc.with_ymd_and_hms(2028, 2, 29, 12, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn cron_next_after_dst() {
+        let cron = SimpleCron::parse_value("30 2 * * *").unwrap();
+
+        // 02:30 does not exist on March 31, the run is skipped
+        let next = cron
+            .next_after(&TestZone.with_ymd_and_hms(2024, 3, 30, 12, 0, 0).unwrap())
+            .unwrap();
+        assert_eq!(
+            next,
+            TestZone.with_ymd_and_hms(2024, 4, 1, 2, 30, 0).unwrap()
+        );
+        assert_eq!(next.offset().local_minus_utc(), 7200);
+
+        // Hourly runs continue across the gap
+        let cron = SimpleCron::parse_value("0 * *").unwrap();
+        let next = cron
+            .next_after(&TestZone.with_ymd_and_hms(2024, 3, 31, 1, 30, 0).unwrap())
+            .unwrap();
+        assert_eq!(next, Utc.with_ymd_and_hms(2024, 3, 31, 1, 0, 0).unwrap());
+        assert_eq!(next.naive_local().hour(), 3);
+
+        // 02:30 happens twice on October 27, only the first one runs
+        let cron = SimpleCron::parse_value("30 2 * * *").unwrap();
+        let next = cron
+            .next_after(&TestZone.with_ymd_and_hms(2024, 10, 27, 0, 0, 0).unwrap())
+            .unwrap();
+        assert_eq!(
+            next,
+            TestZone
+                .with_ymd_and_hms(2024, 10, 27, 2, 30, 0)
+                .earliest()
+                .unwrap()
+        );
+        assert_eq!(next.offset().local_minus_utc(), 7200);
+        let next = cron.next_after(&next).unwrap();
+        assert_eq!(
+            next,
+            TestZone.with_ymd_and_hms(2024, 10, 28, 2, 30, 0).unwrap()
+        );
+        assert_eq!(next.offset().local_minus_utc(), 3600);
+    }
+
+    #[test]
+    fn cron_timezone() {
+        assert_eq!(
+            SimpleCron::parse_value("TZ=Europe/Paris 0 3 *")
+                .unwrap()
+                .schedule(),
+            CronSchedule {
+                timezone: Some(chrono_tz::Europe::Paris),
+                ..SimpleCron::parse_value("0 3 *").unwrap().schedule()
+            }
+        );
+        for expr in ["TZ=Mars/Olympus 0 3 * * *", "TZ=Europe/Paris", "TZ= 0 3 *"] {
+            assert!(SimpleCron::parse_value(expr).is_err(), "{expr:?}");
+        }
+
+        // 03:00 in Paris moves from 02:00 to 01:00 UTC on March 31
+        let next = next_utc(
+            "TZ=Europe/Paris 0 3 * * *",
+            Utc.with_ymd_and_hms(2024, 3, 30, 0, 0, 0).unwrap(),
+        );
+        assert_eq!(next, Utc.with_ymd_and_hms(2024, 3, 30, 2, 0, 0).unwrap());
+        assert_eq!(
+            next_utc("TZ=Europe/Paris 0 3 * * *", next),
+            Utc.with_ymd_and_hms(2024, 3, 31, 1, 0, 0).unwrap()
+        );
+
+        // 02:30 does not exist in Paris on March 31
+        assert_eq!(
+            next_utc(
+                "TZ=Europe/Paris 30 2 * * *",
+                Utc.with_ymd_and_hms(2024, 3, 30, 12, 0, 0).unwrap()
+            ),
+            Utc.with_ymd_and_hms(2024, 4, 1, 0, 30, 0).unwrap()
+        );
+
+        // 02:30 happens twice in Paris on October 27, only the first one runs
+        let next = next_utc(
+            "TZ=Europe/Paris 30 2 * * *",
+            Utc.with_ymd_and_hms(2024, 10, 26, 23, 0, 0).unwrap(),
+        );
+        assert_eq!(next, Utc.with_ymd_and_hms(2024, 10, 27, 0, 30, 0).unwrap());
+        assert_eq!(
+            next_utc("TZ=Europe/Paris 30 2 * * *", next),
+            Utc.with_ymd_and_hms(2024, 10, 28, 1, 30, 0).unwrap()
+        );
+    }
+}
@@ -22,8 +22,11 @@
 */
 
 use std::{
+    borrow::Cow,
     collections::{btree_map::Entry, BTreeMap},
+    fmt::Display,
     iter::Peekable,
+    path::PathBuf,
     str::Chars,
 };
 
@@ -31,6 +34,7 @@ use super::{Config, Result};
 use std::fmt::Write;
 
 const MAX_NEST_LEVEL: usize = 10;
+const LOOP_PREFIX: &str = "for.";
 
 // Simple TOML parser for Stalwart Mail Server configuration files.
 impl Config {
@@ -41,17 +45,294 @@ impl Config {
     }
 
     pub fn parse(&mut self, toml: &str) -> Result<()> {
-        let mut parser = TomlParser::new(&mut self.keys, toml);
+        self.try_parse(toml).map_err(|err| err.to_string())
+    }
+
+    pub fn try_parse(&mut self, toml: &str) -> std::result::Result<(), ParseError> {
+        let (toml, line_map) = expand_loops(toml)?;
+        let mut parser = TomlParser::new(&mut self.keys, &toml, &line_map);
+        parser.parse().map_err(|message| ParseError {
+            path: None,
+            line: parser.line,
+            key: parser.last_key.take(),
+            message,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub path: Option<PathBuf>,
+    pub line: usize,
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl ParseError {
+    pub fn with_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{}: {}", path.display(), self.message)
+        } else {
+            f.write_str(&self.message)
+        }
+    }
+}
+
+// Repeats the body of each `for.<name> in [<item>, ...] { <body> }` block once
+// per item, replacing %{<name>}% with the item. Loops are expanded before the
+// TOML is parsed, so they run ahead of any macro substitution. Along with the
+// expanded TOML, the source line of every expanded line is returned.
+fn expand_loops(toml: &str) -> std::result::Result<(Cow<'_, str>, Vec<usize>), ParseError> {
+    if !toml.contains(LOOP_PREFIX) {
+        return Ok((Cow::Borrowed(toml), Vec::new()));
+    }
+
+    let mut result = String::with_capacity(toml.len());
+    let mut line_map = Vec::new();
+    let mut line = 1;
+    let mut pos = 0;
+    let mut multiline = None;
+    while pos < toml.len() {
+        let line_end = toml[pos..]
+            .find('\n')
+            .map_or(toml.len(), |end| pos + end + 1);
+        let trimmed = toml[pos..line_end].trim_start();
+
+        if multiline.is_none() && trimmed.starts_with(LOOP_PREFIX) {
+            let start = line_end - trimmed.len();
+            let block = match LoopBlock::parse(&toml[start..], line) {
+                Ok(block) => block,
+                Err(message) => {
+                    return Err(ParseError {
+                        path: None,
+                        line,
+                        key: None,
+                        message,
+                    })
+                }
+            };
+            let var_name = format!("%{{{}}}%", block.name);
+            let body = block.body.trim();
+            let body_lines = body.matches('\n').count() + 1;
+            for item in &block.items {
+                result.push_str(&body.replace(&var_name, item));
+                result.push('\n');
+                line_map.extend((0..body_lines).map(|n| line + block.body_line + n));
+            }
+            line += toml[start..start + block.len].matches('\n').count();
+            pos = start + block.len;
+        } else {
+            multiline = multiline_string(&toml[pos..line_end], multiline);
+            result.push_str(&toml[pos..line_end]);
+            line_map.push(line);
+            line += 1;
+            pos = line_end;
+        }
+    }
+
+    Ok((Cow::Owned(result), line_map))
+}
+
+// Returns the delimiter of the multi-line string still open at the end of the line
+fn multiline_string(line: &str, mut open: Option<&'static str>) -> Option<&'static str> {
+    let mut line = line;
+    loop {
+        if let Some(delimiter) = open {
+            match line.find(delimiter) {
+                Some(pos) => {
+                    line = &line[pos + delimiter.len()..];
+                    open = None;
+                }
+                None => return open,
+            }
+        } else {
+            match ["\"\"\"", "'''"]
+                .into_iter()
+                .filter_map(|delimiter| line.find(delimiter).map(|pos| (pos, delimiter)))
+                .min()
+            {
+                Some((pos, delimiter)) => {
+                    line = &line[pos + delimiter.len()..];
+                    open = Some(delimiter);
+                }
+                None => return None,
+            }
+        }
+    }
+}
+
+struct LoopBlock<'x> {
+    name: &'x str,
+    items: Vec<&'x str>,
+    body: &'x str,
+    body_line: usize,
+    len: usize,
+}
+
+impl<'x> LoopBlock<'x> {
+    fn parse(block: &'x str, line: usize) -> Result<Self> {
+        let invalid = |reason: &str| format!("Invalid loop at line {line}: {reason}.");
+
+        // Variable name
+        let header = &block[LOOP_PREFIX.len()..];
+        let name_len = header
+            .find(|ch: char| !(ch.is_alphanumeric() || ['-', '_'].contains(&ch)))
+            .unwrap_or(header.len());
+        let name = &header[..name_len];
+        if name.is_empty() {
+            return Err(invalid("expected a variable name after 'for.'"));
+        }
+        let header = match header[name_len..]
+            .trim_start_matches([' ', '\t'])
+            .strip_prefix("in")
+        {
+            Some(header) if header.starts_with([' ', '\t']) => {
+                header.trim_start_matches([' ', '\t'])
+            }
+            _ => return Err(invalid("expected 'in' after the variable name")),
+        };
+
+        // Items
+        let header = match header.strip_prefix('[') {
+            Some(header) => header,
+            None => return Err(invalid("expected a list of items after 'in'")),
+        };
+        let (list, header) = match header.split_once(']') {
+            Some((list, header)) if !list.contains('\n') => (list, header),
+            _ => return Err(invalid("unterminated list of items")),
+        };
+        let items = list
+            .split(',')
+            .map(|item| item.trim())
+            .filter(|item| !item.is_empty())
+            .map(|item| {
+                item.strip_prefix('"')
+                    .and_then(|item| item.strip_suffix('"'))
+                    .unwrap_or(item)
+            })
+            .collect::<Vec<_>>();
+
+        // Body, up to the closing brace outside of strings and comments
+        let body = match header.trim_start_matches([' ', '\t']).strip_prefix('{') {
+            Some(body) => body,
+            None => return Err(invalid("expected '{' after the list of items")),
+        };
+        let mut depth = 0;
+        let mut quote = None;
+        let mut escaped = false;
+        let mut in_comment = false;
+        let mut body_len = None;
+        for (idx, ch) in body.char_indices() {
+            if in_comment {
+                in_comment = ch != '\n';
+            } else if let Some(qch) = quote {
+                if escaped {
+                    escaped = false;
+                } else if ch == '\\' && qch == '"' {
+                    escaped = true;
+                } else if ch == qch {
+                    quote = None;
+                }
+            } else {
+                match ch {
+                    '"' | '\'' => quote = Some(ch),
+                    '#' => in_comment = true,
+                    '{' => depth += 1,
+                    '}' if depth == 0 => {
+                        body_len = Some(idx);
+                        break;
+                    }
+                    '}' => depth -= 1,
+                    _ => (),
+                }
+            }
+        }
+        let body_len = match body_len {
+            Some(body_len) => body_len,
+            None => return Err(invalid("missing closing '}'")),
+        };
+        let body_start = block.len() - body.len();
+        let (body, rest) = (&body[..body_len], &body[body_len + 1..]);
+        if body
+            .lines()
+            .any(|line| line.trim_start().starts_with(LOOP_PREFIX))
+        {
+            return Err(invalid("nested loops are not supported"));
+        }
+
+        // Only a comment may follow the closing brace
+        let rest_len = rest.find('\n').map_or(rest.len(), |end| end + 1);
+        let trailing = rest[..rest_len].trim();
+        if !trailing.is_empty() && !trailing.starts_with('#') {
+            return Err(invalid("unexpected characters after '}'"));
+        }
+
+        // Line of the first body line, relative to the loop
+        let body_line = block[..body_start + body.len() - body.trim_start().len()]
+            .matches('\n')
+            .count();
+
+        Ok(LoopBlock {
+            name,
+            items,
+            body,
+            body_line,
+            len: block.len() - rest.len() + rest_len,
+        })
+    }
+}
+
+struct TomlParser<'x, 'y> {
+    keys: &'y mut BTreeMap<String, String>,
+    iter: Peekable<Chars<'x>>,
+    line: usize,
+    line_map: &'x [usize],
+    expanded_line: usize,
+    last_key: Option<String>,
+}
+
+impl<'x, 'y> TomlParser<'x, 'y> {
+    fn new(keys: &'y mut BTreeMap<String, String>, toml: &'x str, line_map: &'x [usize]) -> Self {
+        Self {
+            keys,
+            iter: toml.chars().peekable(),
+            line: line_map.first().copied().unwrap_or(1),
+            line_map,
+            expanded_line: 1,
+            last_key: None,
+        }
+    }
+
+    // Errors report the line in the source TOML, before loops were expanded
+    fn new_line(&mut self) {
+        self.expanded_line += 1;
+        self.line = match self.line_map.get(self.expanded_line - 1) {
+            Some(line) => *line,
+            None => self.line_map.last().map_or(self.expanded_line, |line| {
+                line + self.expanded_line - self.line_map.len()
+            }),
+        };
+    }
+
+    fn parse(&mut self) -> Result<()> {
         let mut table_name = String::new();
         let mut last_array_name = String::new();
         let mut last_array_pos = 0;
 
-        while parser.seek_next_char() {
-            match parser.peek_char()? {
+        while self.seek_next_char() {
+            match self.peek_char()? {
                 '[' => {
-                    parser.next_char(true, false)?;
+                    self.last_key = None;
+                    self.next_char(true, false)?;
                     table_name.clear();
-                    let mut is_array = match parser.next_char(true, false)? {
+                    let mut is_array = match self.next_char(true, false)? {
                         '[' => true,
                         ch => {
                             table_name.push(ch);
@@ -61,7 +342,7 @@ impl Config {
                     let mut in_quote = false;
                     let mut last_ch = char::from(0);
                     loop {
-                        let ch = parser.next_char(!in_quote, false)?;
+                        let ch = self.next_char(!in_quote, false)?;
                         match ch {
                             '\"' if !in_quote || last_ch != '\\' => {
                                 in_quote = !in_quote;
@@ -69,10 +350,7 @@ impl Config {
                             '\\' if in_quote => (),
                             ']' if !in_quote => {
                                 if table_name.is_empty() {
-                                    return Err(format!(
-                                        "Empty table name at line {}.",
-                                        parser.line
-                                    ));
+                                    return Err(format!("Empty table name at line {}.", self.line));
                                 }
                                 if is_array {
                                     if table_name == last_array_name {
@@ -94,7 +372,7 @@ impl Config {
                                     } else {
                                         return Err(format!(
                                             "Unexpected character {:?} at line {}.",
-                                            ch, parser.line
+                                            ch, self.line
                                         ));
                                     }
                                 } else {
@@ -104,10 +382,11 @@ impl Config {
                         }
                         last_ch = ch;
                     }
-                    parser.skip_line();
+                    self.skip_line();
                 }
                 'a'..='z' | 'A'..='Z' | '0'..='9' | '\"' => {
-                    let (key, _) = parser.key(
+                    self.last_key = None;
+                    let (key, _) = self.key(
                         if !table_name.is_empty() {
                             format!("{table_name}.")
                         } else {
@@ -115,16 +394,17 @@ impl Config {
                         },
                         false,
                     )?;
-                    parser.value(key, &['\n'], 0)?;
+                    self.last_key = Some(key.clone());
+                    self.value(key, &['\n'], 0)?;
                 }
                 '#' => {
-                    parser.skip_line();
+                    self.skip_line();
                 }
                 ch => {
                     let ch = *ch;
                     return Err(format!(
                         "Unexpected character {:?} at line {}.",
-                        ch, parser.line
+                        ch, self.line
                     ));
                 }
             }
@@ -132,29 +412,13 @@ impl Config {
 
         Ok(())
     }
-}
-
-struct TomlParser<'x, 'y> {
-    keys: &'y mut BTreeMap<String, String>,
-    iter: Peekable<Chars<'x>>,
-    line: usize,
-}
-
-impl<'x, 'y> TomlParser<'x, 'y> {
-    fn new(keys: &'y mut BTreeMap<String, String>, toml: &'x str) -> Self {
-        Self {
-            keys,
-            iter: toml.chars().peekable(),
-            line: 1,
-        }
-    }
 
     fn seek_next_char(&mut self) -> bool {
         while let Some(ch) = self.iter.peek() {
             match ch {
                 '\n' => {
                     self.iter.next();
-                    self.line += 1;
+                    self.new_line();
                 }
                 '\r' | ' ' | '\t' => {
                     self.iter.next();
@@ -182,7 +446,7 @@ impl<'x, 'y> TomlParser<'x, 'y> {
                 ' ' | '\t' if skip_wsp => (),
                 '\n' => {
                     return if allow_lf {
-                        self.line += 1;
+                        self.new_line();
                         Ok(ch)
                     } else {
                         Err(format!("Unexpected end of line at line: {}", self.line))
@@ -199,7 +463,7 @@ impl<'x, 'y> TomlParser<'x, 'y> {
     fn skip_line(&mut self) {
         for ch in &mut self.iter {
             if ch == '\n' {
-                self.line += 1;
+                self.new_line();
                 break;
             }
         }
@@ -251,7 +515,7 @@ impl<'x, 'y> TomlParser<'x, 'y> {
                 ' ' | '\t' | '\r' => (),
                 '\n' => {
                     if start_key_len == key.len() {
-                        self.line += 1;
+                        self.new_line();
                     } else {
                         return Err(format!(
                             "Unexpected end of line while parsing key {:?} at line: {}",
@@ -347,7 +611,7 @@ impl<'x, 'y> TomlParser<'x, 'y> {
                         self.iter.next();
                         if matches!(self.iter.peek(), Some(ch) if ch == &'\n') {
                             self.iter.next();
-                            self.line += 1;
+                            self.new_line();
                         }
 
                         let mut last_ch = char::from(0);
@@ -460,7 +724,7 @@ impl<'x, 'y> TomlParser<'x, 'y> {
 mod tests {
     use std::{collections::BTreeMap, fs, path::PathBuf};
 
-    use crate::config::Config;
+    use crate::config::{parser::ParseError, Config};
 
     #[test]
     fn toml_parse() {
@@ -608,4 +872,116 @@ mod tests {
             vec!["string", "strings"]
         );
     }
+
+    #[test]
+    fn toml_loops() {
+        let config = Config::new(concat!(
+            "[server]\n",
+            "hostname = \"mx.example.org\"\n",
+            "for.port in [25, \"465\"] {\n",
+            "  listener.smtp-%{port}%.bind = \"0.0.0.0:%{port}%\"\n",
+            "  listener.smtp-%{port}%.protocol = \"smtp\" # braces } in comments\n",
+            "}\n",
+            "for.id in [a, b] { listener.%{id}%.protocol = \"imap\" }\n",
+            "max-connections = 100\n",
+        ))
+        .unwrap();
+
+        assert_eq!(
+            config.keys,
+            BTreeMap::from_iter(
+                [
+                    ("server.hostname", "mx.example.org"),
+                    ("server.listener.a.protocol", "imap"),
+                    ("server.listener.b.protocol", "imap"),
+                    ("server.listener.smtp-25.bind", "0.0.0.0:25"),
+                    ("server.listener.smtp-25.protocol", "smtp"),
+                    ("server.listener.smtp-465.bind", "0.0.0.0:465"),
+                    ("server.listener.smtp-465.protocol", "smtp"),
+                    ("server.max-connections", "100"),
+                ]
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+            )
+        );
+    }
+
+    #[test]
+    fn toml_parse_errors() {
+        for (toml, line, key, message) in [
+            (
+                "a = 1\n\n[server\nb = 2\n",
+                3,
+                None,
+                "Unexpected end of line at line: 3",
+            ),
+            (
+                "[server]\nhostname = \"mx\"\nhostname = \"mx2\"\n",
+                3,
+                Some("server.hostname"),
+                "Duplicate key \"server.hostname\" at line 3.",
+            ),
+            (
+                "[server]\nports = [25, 587\n",
+                3,
+                Some("server.ports"),
+                "Unexpected EOF at line: 3",
+            ),
+            (
+                "a = 1\nfor.port [25] {\nb = %{port}%\n}\n",
+                2,
+                None,
+                "Invalid loop at line 2: expected 'in' after the variable name.",
+            ),
+            (
+                "for.port in [25, 587] {\nb = %{port}%\n",
+                1,
+                None,
+                "Invalid loop at line 1: missing closing '}'.",
+            ),
+            (
+                "for.a in [1] {\nfor.b in [2] {\nc = 3\n}\n}\n",
+                1,
+                None,
+                "Invalid loop at line 1: nested loops are not supported.",
+            ),
+            (
+                "for.port in [25, 587] {\n  a.%{port}% = 1\n}\n\n[server\nb = 2\n",
+                5,
+                None,
+                "Unexpected end of line at line: 5",
+            ),
+            (
+                "for.id in [a, a] {\n  # Comment\n  b.%{id}% = 1\n}\n",
+                3,
+                Some("b.a"),
+                "Duplicate key \"b.a\" at line 3.",
+            ),
+            (
+                "a = '''\nfor.x in [1] {\n'''\nb = 1\nb = 2\n",
+                5,
+                Some("b"),
+                "Duplicate key \"b\" at line 5.",
+            ),
+        ] {
+            let err = Config::default().try_parse(toml).unwrap_err();
+            assert_eq!(
+                err,
+                ParseError {
+                    path: None,
+                    line,
+                    key: key.map(|k| k.to_string()),
+                    message: message.to_string(),
+                },
+                "failed for {toml:?}"
+            );
+
+            // Display matches the unstructured error
+            assert_eq!(Config::new(toml).unwrap_err(), message);
+            assert_eq!(
+                err.with_path("/etc/stalwart.toml").to_string(),
+                format!("/etc/stalwart.toml: {message}")
+            );
+        }
+    }
 }
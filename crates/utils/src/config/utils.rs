@@ -22,7 +22,9 @@
 */
 
 use std::{
+    collections::btree_map,
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    ops::Bound,
     path::PathBuf,
     time::Duration,
 };
@@ -128,22 +130,44 @@ impl Config {
         let mut last_key = "";
         let prefix = prefix.as_prefix();
 
-        self.keys.keys().filter_map(move |key| {
-            let key = key.strip_prefix(&prefix)?;
-            let key = if !suffix.is_empty() {
-                key.strip_suffix(suffix)?
-            } else if let Some((key, _)) = key.split_once('.') {
-                key
-            } else {
-                key
-            };
-            if last_key != key {
-                last_key = key;
-                Some(key)
-            } else {
-                None
-            }
-        })
+        self.key_range(&prefix)
+            .map_while(move |(key, _)| key.strip_prefix(&prefix))
+            .filter_map(move |key| {
+                let key = if !suffix.is_empty() {
+                    key.strip_suffix(suffix)?
+                } else if let Some((key, _)) = key.split_once('.') {
+                    key
+                } else {
+                    key
+                };
+                if last_key != key {
+                    last_key = key;
+                    Some(key)
+                } else {
+                    None
+                }
+            })
+    }
+
+    // Returns all keys starting with the prefix along with their values, in key order
+    pub fn prefix<'x, 'y: 'x>(
+        &'y self,
+        prefix: impl AsKey,
+    ) -> impl Iterator<Item = (&'y str, &'y str)> + 'x {
+        let prefix = prefix.as_prefix();
+
+        #[cfg(debug_assertions)]
+        self.keys_read.lock().insert(prefix.clone());
+
+        self.key_range(&prefix)
+            .take_while(move |(key, _)| key.starts_with(&prefix))
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    // Keys are sorted, so all keys sharing a prefix are found by seeking to the prefix
+    fn key_range(&self, prefix: &str) -> btree_map::Range<'_, String, String> {
+        self.keys
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
     }
 
     pub fn set_values<'x, 'y: 'x>(&'y self, prefix: impl AsKey) -> impl Iterator<Item = &str> + 'x {
@@ -191,6 +215,15 @@ impl Config {
         self.keys.get(&key).map(|s| s.as_str())
     }
 
+    // Lowercases the key before lookup, meant for use with "config.case-insensitive"
+    pub fn value_ignore_case(&self, key: impl AsKey) -> Option<&str> {
+        self.value(key.as_key().to_lowercase())
+    }
+
+    pub fn property_ignore_case<T: ParseValue>(&mut self, key: impl AsKey) -> Option<T> {
+        self.property(key.as_key().to_lowercase())
+    }
+
     pub fn contains_key(&self, key: impl AsKey) -> bool {
         self.keys.contains_key(&key.as_key())
     }
@@ -290,7 +323,9 @@ impl Config {
 
     pub fn has_prefix(&self, prefix: impl AsKey) -> bool {
         let prefix = prefix.as_prefix();
-        self.keys.keys().any(|k| k.starts_with(&prefix))
+        self.key_range(&prefix)
+            .next()
+            .is_some_and(|(k, _)| k.starts_with(&prefix))
     }
 
     pub fn new_parse_error(&mut self, key: impl AsKey, details: impl Into<String>) {
@@ -526,37 +561,62 @@ impl ParseValue for HashAlgorithm {
 
 impl ParseValue for Duration {
     fn parse_value(value: &str) -> super::Result<Self> {
-        let mut digits = String::new();
-        let mut multiplier = String::new();
-
-        for ch in value.chars() {
-            if ch.is_ascii_digit() {
-                digits.push(ch);
-            } else if !ch.is_ascii_whitespace() {
-                multiplier.push(ch.to_ascii_lowercase());
+        let invalid = |reason: &str| format!("Invalid duration value {value:?}, {reason}.");
+        let trimmed = value.trim();
+
+        // A bare number is a number of milliseconds, otherwise each number
+        // is followed by its unit, largest unit first, as in "1h30m"
+        let millis = if let Ok(millis) = trimmed.parse::<u64>() {
+            millis
+        } else if !trimmed.is_empty() {
+            let mut millis = 0u64;
+            let mut last_multiplier = u64::MAX;
+            let mut chars = trimmed.chars().peekable();
+
+            while chars.peek().is_some() {
+                let mut digits = String::new();
+                let mut unit = String::new();
+                while let Some(ch) = chars.next_if(|ch| ch.is_ascii_digit()) {
+                    digits.push(ch);
+                }
+                while chars.next_if(|ch| ch.is_ascii_whitespace()).is_some() {}
+                while let Some(ch) = chars.next_if(|ch| ch.is_ascii_alphabetic()) {
+                    unit.push(ch.to_ascii_lowercase());
+                }
+                while chars.next_if(|ch| ch.is_ascii_whitespace()).is_some() {}
+
+                let multiplier = match unit.as_str() {
+                    "d" => 24 * 60 * 60 * 1000,
+                    "h" => 60 * 60 * 1000,
+                    "m" => 60 * 1000,
+                    "s" => 1000,
+                    "ms" => 1,
+                    _ => 0,
+                };
+                if digits.is_empty() || multiplier == 0 {
+                    return Err(invalid("expected a number followed by d, h, m, s or ms"));
+                } else if multiplier >= last_multiplier {
+                    return Err(invalid("each unit may appear once, largest first"));
+                }
+                last_multiplier = multiplier;
+                millis = digits
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|num| num.checked_mul(multiplier))
+                    .and_then(|num| num.checked_add(millis))
+                    .ok_or_else(|| invalid("value is too large"))?;
             }
-        }
 
-        let multiplier = match multiplier.as_str() {
-            "d" => 24 * 60 * 60 * 1000,
-            "h" => 60 * 60 * 1000,
-            "m" => 60 * 1000,
-            "s" => 1000,
-            "ms" | "" => 1,
-            _ => return Err(format!("Invalid duration value {:?}.", value)),
+            millis
+        } else {
+            return Err(invalid("expected a number followed by d, h, m, s or ms"));
         };
 
-        digits
-            .parse::<u64>()
-            .ok()
-            .and_then(|num| {
-                if num > 0 {
-                    Some(Duration::from_millis(num * multiplier))
-                } else {
-                    None
-                }
-            })
-            .ok_or_else(|| format!("Invalid duration value {:?}.", value))
+        if millis > 0 {
+            Ok(Duration::from_millis(millis))
+        } else {
+            Err(invalid("expected a duration greater than zero"))
+        }
     }
 }
 
@@ -569,13 +629,29 @@ impl ParseValue for Rate {
                     .parse::<u64>()
                     .ok()
                     .and_then(|r| if r > 0 { Some(r) } else { None })
-                    .ok_or_else(|| format!("Invalid rate value {:?}.", value))?,
-                period: std::cmp::max(Duration::parse_value(period)?, Duration::from_secs(1)),
+                    .ok_or_else(|| {
+                        format!(
+                            "Invalid rate value {:?}, expected a positive number of requests.",
+                            value
+                        )
+                    })?,
+                period: std::cmp::max(
+                    Duration::parse_value(period.trim()).map_err(|_| {
+                        format!(
+                            "Invalid rate value {:?}, expected a non-zero period.",
+                            value
+                        )
+                    })?,
+                    Duration::from_secs(1),
+                ),
             })
         } else if ["false", "none", "unlimited"].contains(&value) {
             Ok(Rate::default())
         } else {
-            Err(format!("Invalid rate value {:?}.", value))
+            Err(format!(
+                "Invalid rate value {:?}, expected \"<requests>/<period>\".",
+                value
+            ))
         }
     }
 }
@@ -673,9 +749,9 @@ impl AsKey for (&str, &str, &str, &str) {
 
 #[cfg(test)]
 mod tests {
-    use std::net::IpAddr;
+    use std::{net::IpAddr, time::Duration};
 
-    use crate::config::Config;
+    use crate::config::{utils::ParseValue, Config, Rate};
 
     #[test]
     fn toml_utils() {
@@ -733,4 +809,131 @@ ip = "a:b::1:1"
             "a:b::1:1".parse::<IpAddr>().unwrap()
         );
     }
+
+    #[test]
+    fn prefix_scan() {
+        let toml = r#"
+server.hostname = "mx.example.org"
+
+[server.listener."smtp"]
+bind = ["0.0.0.0:25"]
+protocol = "smtp"
+
+[server.listener."imap"]
+bind = ["0.0.0.0:143"]
+protocol = "imap"
+
+[server-extra]
+value = "not a child of server"
+
+[session]
+timeout = "5m"
+"#;
+        let mut config = Config::default();
+        config.parse(toml).unwrap();
+
+        assert_eq!(
+            config.prefix("server").collect::<Vec<_>>(),
+            [
+                ("server.hostname", "mx.example.org"),
+                ("server.listener.imap.bind.0000", "0.0.0.0:143"),
+                ("server.listener.imap.protocol", "imap"),
+                ("server.listener.smtp.bind.0000", "0.0.0.0:25"),
+                ("server.listener.smtp.protocol", "smtp"),
+            ]
+        );
+        assert_eq!(config.prefix(("server", "listener", "smtp")).count(), 2);
+        assert_eq!(config.prefix("queue").count(), 0);
+        assert_eq!(
+            config.sub_keys("server", "").collect::<Vec<_>>(),
+            ["hostname", "listener"]
+        );
+        assert_eq!(
+            config.sub_keys("server.listener", "").collect::<Vec<_>>(),
+            ["imap", "smtp"]
+        );
+        assert_eq!(
+            config
+                .sub_keys("server.listener", ".protocol")
+                .collect::<Vec<_>>(),
+            ["imap", "smtp"]
+        );
+        assert!(config.has_prefix("server.listener"));
+        assert!(!config.has_prefix("server.listener.pop3"));
+        assert!(!config.has_prefix("listener"));
+    }
+
+    #[test]
+    fn parse_rate() {
+        for (value, requests, period) in [
+            ("5/1s", 5, Duration::from_secs(1)),
+            ("1000/1m", 1000, Duration::from_secs(60)),
+            ("50/1h", 50, Duration::from_secs(60 * 60)),
+            ("100/1d", 100, Duration::from_secs(24 * 60 * 60)),
+            (" 10 / 30s ", 10, Duration::from_secs(30)),
+        ] {
+            assert_eq!(
+                Rate::parse_value(value).unwrap(),
+                Rate { requests, period },
+                "failed for {value:?}"
+            );
+        }
+
+        for value in [
+            "abc/1m", "0/1m", "10/0s", "10/1y", "10/", "/1m", "10", "10m",
+        ] {
+            assert!(Rate::parse_value(value).is_err(), "failed for {value:?}");
+        }
+    }
+
+    #[test]
+    fn parse_duration() {
+        for (value, expected) in [
+            ("500", Duration::from_millis(500)),
+            ("500ms", Duration::from_millis(500)),
+            ("90s", Duration::from_secs(90)),
+            ("15m", Duration::from_secs(15 * 60)),
+            ("2h", Duration::from_secs(2 * 60 * 60)),
+            ("2d", Duration::from_secs(2 * 24 * 60 * 60)),
+            ("1h30m", Duration::from_secs(90 * 60)),
+            ("1d12h", Duration::from_secs(36 * 60 * 60)),
+            ("1m30s500ms", Duration::from_millis(90_500)),
+            ("1h 30m", Duration::from_secs(90 * 60)),
+            (" 10 s ", Duration::from_secs(10)),
+            ("10S", Duration::from_secs(10)),
+            ("0h5m", Duration::from_secs(5 * 60)),
+        ] {
+            assert_eq!(
+                Duration::parse_value(value).unwrap(),
+                expected,
+                "failed for {value:?}"
+            );
+        }
+
+        for value in [
+            "",
+            "   ",
+            "0",
+            "0s",
+            "0h0m",
+            "s",
+            "ms",
+            "10x",
+            "10 sec",
+            "1.5h",
+            "-5s",
+            "1h30",
+            "30m1h",
+            "1m1m",
+            "1h 2h",
+            "5s 10",
+            "99999999999999999999s",
+            "999999999999d",
+        ] {
+            assert!(
+                Duration::parse_value(value).is_err(),
+                "failed for {value:?}"
+            );
+        }
+    }
 }
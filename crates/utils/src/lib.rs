@@ -193,7 +193,7 @@ pub fn rustls_client_config(allow_invalid_certs: bool) -> ClientConfig {
 }
 
 #[derive(Debug)]
-struct DummyVerifier;
+pub struct DummyVerifier;
 
 impl ServerCertVerifier for DummyVerifier {
     fn verify_server_cert(
@@ -57,7 +57,7 @@ use smtp::core::{SmtpSessionManager, SMTP};
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines, ReadHalf, WriteHalf},
     net::TcpStream,
-    sync::{mpsc, watch},
+    sync::mpsc,
 };
 use utils::config::Config;
 
@@ -262,7 +262,7 @@ pub struct IMAPTest {
     jmap: Arc<JMAP>,
     imap: Arc<Inner>,
     temp_dir: TempDir,
-    shutdown_tx: watch::Sender<bool>,
+    servers: Servers,
 }
 
 async fn init_imap_tests(store_id: &str, delete_if_exists: bool) -> IMAPTest {
@@ -280,7 +280,7 @@ async fn init_imap_tests(store_id: &str, delete_if_exists: bool) -> IMAPTest {
     let mut servers = Servers::parse(&mut config);
 
     // Bind ports and drop privileges
-    servers.bind_and_drop_priv(&mut config);
+    servers.bind_and_drop_priv(&mut config).await;
 
     // Build stores
     let stores = Stores::parse_all(&mut config).await;
@@ -307,7 +307,7 @@ async fn init_imap_tests(store_id: &str, delete_if_exists: bool) -> IMAPTest {
     config.assert_no_errors();
 
     // Spawn servers
-    let (shutdown_tx, _) = servers.spawn(|server, acceptor, shutdown_rx| {
+    servers.spawn(|server, acceptor, shutdown_rx| {
         match &server.protocol {
             ServerProtocol::Smtp | ServerProtocol::Lmtp => server.spawn(
                 SmtpSessionManager::new(smtp.clone()),
@@ -333,6 +333,7 @@ async fn init_imap_tests(store_id: &str, delete_if_exists: bool) -> IMAPTest {
                 acceptor,
                 shutdown_rx,
             ),
+            ServerProtocol::Pop3 => unreachable!(),
         };
     });
     // Create tables and test accounts
@@ -376,7 +377,7 @@ async fn init_imap_tests(store_id: &str, delete_if_exists: bool) -> IMAPTest {
         jmap: JMAP::from(jmap.clone()).into(),
         imap: imap.imap_inner,
         temp_dir,
-        shutdown_tx,
+        servers,
     }
 }
 
@@ -0,0 +1,89 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use super::JMAPTest;
+
+pub async fn test(_params: &mut JMAPTest) {
+    println!("Running HTTP request size limit tests...");
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_millis(1000))
+        .build()
+        .unwrap();
+
+    // Bodies over the listener's limit are rejected before being read
+    let response = client
+        .post("http://127.0.0.1:8898/jmap")
+        .body(vec![b'a'; 2048])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // The listener keeps serving requests within the limit
+    let response = client
+        .post("http://127.0.0.1:8898/jmap")
+        .body("{}")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // Chunked bodies are limited while they are read
+    let mut stream = TcpStream::connect("127.0.0.1:8898").await.unwrap();
+    stream
+        .write_all(
+            concat!(
+                "POST /autodiscover/autodiscover.xml HTTP/1.1\r\n",
+                "Host: 127.0.0.1\r\n",
+                "Transfer-Encoding: chunked\r\n\r\n"
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+    for _ in 0..4 {
+        stream.write_all(b"200\r\n").await.unwrap();
+        stream.write_all(&[b'a'; 512]).await.unwrap();
+        stream.write_all(b"\r\n").await.unwrap();
+    }
+    stream.write_all(b"0\r\n\r\n").await.unwrap();
+    let mut response = vec![0u8; 1024];
+    let size = tokio::time::timeout(Duration::from_millis(1000), stream.read(&mut response))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(
+        response[..size].starts_with(b"HTTP/1.1 413"),
+        "{}",
+        String::from_utf8_lossy(&response[..size])
+    );
+}
@@ -47,7 +47,7 @@ use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use smtp::core::{SmtpSessionManager, SMTP};
 
 use store::Stores;
-use tokio::sync::{mpsc, watch};
+use tokio::sync::mpsc;
 use utils::config::Config;
 
 use crate::{add_test_certs, directory::DirectoryStore, store::TempDir, AssertConfig};
@@ -68,6 +68,7 @@ pub mod email_search_snippet;
 pub mod email_set;
 pub mod email_submission;
 pub mod event_source;
+pub mod http_limits;
 pub mod mailbox;
 pub mod push_subscription;
 pub mod quota;
@@ -89,6 +90,11 @@ protocol = "http"
 max-connections = 81920
 tls.implicit = true
 
+[server.listener.jmap-limited]
+bind = ["127.0.0.1:8898"]
+protocol = "http"
+max-request-size = 1024
+
 [server.listener.imap]
 bind = ["127.0.0.1:9991"]
 protocol = "imap"
@@ -326,6 +332,7 @@ pub async fn jmap_tests() {
     quota::test(&mut params).await;
     crypto::test(&mut params).await;
     blob::test(&mut params).await;
+    http_limits::test(&mut params).await;
 
     if delete {
         params.temp_dir.delete();
@@ -366,7 +373,7 @@ pub struct JMAPTest {
     client: Client,
     directory: DirectoryStore,
     temp_dir: TempDir,
-    shutdown_tx: watch::Sender<bool>,
+    servers: Servers,
 }
 
 pub async fn wait_for_index(server: &JMAP) {
@@ -414,7 +421,7 @@ async fn init_jmap_tests(store_id: &str, delete_if_exists: bool) -> JMAPTest {
     let mut servers = Servers::parse(&mut config);
 
     // Bind ports and drop privileges
-    servers.bind_and_drop_priv(&mut config);
+    servers.bind_and_drop_priv(&mut config).await;
 
     // Build stores
     let stores = Stores::parse_all(&mut config).await;
@@ -429,6 +436,8 @@ async fn init_jmap_tests(store_id: &str, delete_if_exists: bool) -> JMAPTest {
             .and_then(|id| stores.stores.get(id))
             .cloned()
             .unwrap_or_default(),
+        cfg_profile: None,
+        cfg_base_paths: vec![],
     };
     let core = Core::parse(&mut config, stores, config_manager).await;
     let store = core.storage.data.clone();
@@ -451,7 +460,7 @@ async fn init_jmap_tests(store_id: &str, delete_if_exists: bool) -> JMAPTest {
     config.assert_no_errors();
 
     // Spawn servers
-    let (shutdown_tx, _) = servers.spawn(|server, acceptor, shutdown_rx| {
+    servers.spawn(|server, acceptor, shutdown_rx| {
         match &server.protocol {
             ServerProtocol::Smtp | ServerProtocol::Lmtp => server.spawn(
                 SmtpSessionManager::new(smtp.clone()),
@@ -477,6 +486,7 @@ async fn init_jmap_tests(store_id: &str, delete_if_exists: bool) -> JMAPTest {
                 acceptor,
                 shutdown_rx,
             ),
+            ServerProtocol::Pop3 => unreachable!(),
         };
     });
 
@@ -514,7 +524,7 @@ async fn init_jmap_tests(store_id: &str, delete_if_exists: bool) -> JMAPTest {
         temp_dir,
         client,
         directory,
-        shutdown_tx,
+        servers,
     }
 }
 
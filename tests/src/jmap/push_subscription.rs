@@ -125,9 +125,9 @@ pub async fn test(params: &mut JMAPTest) {
 
     // Start JMAP server
     let manager = SessionManager::from(push_server.clone());
-    servers.bind_and_drop_priv(&mut settings);
+    servers.bind_and_drop_priv(&mut settings).await;
     settings.assert_no_errors();
-    let _shutdown_tx = servers.spawn(|server, acceptor, shutdown_rx| {
+    let _shutdown_rx = servers.spawn(|server, acceptor, shutdown_rx| {
         server.spawn(manager.clone(), mock_core.clone(), acceptor, shutdown_rx);
     });
 
@@ -21,19 +21,43 @@
  * for more details.
 */
 
-use std::{fs, net::IpAddr, path::PathBuf, time::Duration};
+use std::{
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
 use common::{
     config::{
-        server::{Listener, Server, ServerProtocol, Servers},
+        scripts::Scripting,
+        server::{
+            tls::parse_sni_certificates, Listener, ListenerOptions, ListenerSettings, Server,
+            ServerProtocol, Servers, UNIX_PEER_ADDR,
+        },
         smtp::{throttle::parse_throttle, *},
     },
     expr::{functions::ResolveVariable, if_block::*, tokenizer::TokenMap, *},
+    listener::{
+        stream::TimeoutStream, tls::CertificateResolver, SessionData, SessionManager,
+        SessionStream, TcpAcceptor,
+    },
     Core,
 };
-use tokio::net::TcpSocket;
+use rustls_pki_types::ServerName;
+use store::Stores;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpSocket, TcpStream},
+    sync::watch,
+};
+use tokio_rustls::TlsConnector;
 
-use utils::config::{Config, Rate};
+use utils::{
+    config::{ipmask::IpAddrMask, utils::ParseValue, Config, ConfigError, ConfigWarning, Rate},
+    DummyVerifier,
+};
 
 use super::add_test_certs;
 
@@ -328,9 +352,21 @@ fn parse_servers() {
                 backlog: 1024.into(),
                 linger: None,
                 nodelay: true,
+                reuse_port: true,
+                keepalive: None,
+                ipv6_only: None,
+                proxy_protocol: false,
+                accept_rate: None,
+                read_timeout: None,
+                write_timeout: None,
             }],
+            unix_listeners: vec![],
             max_connections: 8192,
+            max_connections_per_ip: None,
+            max_request_size: None,
             proxy_networks: vec![],
+            allowed_networks: vec![],
+            denied_networks: vec![],
         },
         Server {
             id: "smtps".to_string(),
@@ -343,6 +379,13 @@ fn parse_servers() {
                     backlog: 1024.into(),
                     linger: None,
                     nodelay: true,
+                    reuse_port: true,
+                    keepalive: None,
+                    ipv6_only: None,
+                    proxy_protocol: true,
+                    accept_rate: None,
+                    read_timeout: None,
+                    write_timeout: None,
                 },
                 Listener {
                     socket: TcpSocket::new_v4().unwrap(),
@@ -351,10 +394,22 @@ fn parse_servers() {
                     backlog: 1024.into(),
                     linger: None,
                     nodelay: true,
+                    reuse_port: true,
+                    keepalive: None,
+                    ipv6_only: None,
+                    proxy_protocol: true,
+                    accept_rate: None,
+                    read_timeout: None,
+                    write_timeout: None,
                 },
             ],
+            unix_listeners: vec![],
             max_connections: 1024,
-            proxy_networks: vec![],
+            max_connections_per_ip: None,
+            max_request_size: None,
+            proxy_networks: vec![IpAddrMask::parse_value("127.0.0.0/8").unwrap()],
+            allowed_networks: vec![],
+            denied_networks: vec![],
         },
         Server {
             id: "submission".to_string(),
@@ -366,9 +421,21 @@ fn parse_servers() {
                 backlog: 2048.into(),
                 linger: None,
                 nodelay: true,
+                reuse_port: true,
+                keepalive: Duration::from_secs(300).into(),
+                ipv6_only: None,
+                proxy_protocol: false,
+                accept_rate: None,
+                read_timeout: None,
+                write_timeout: None,
             }],
+            unix_listeners: vec![],
             max_connections: 8192,
+            max_connections_per_ip: None,
+            max_request_size: None,
             proxy_networks: vec![],
+            allowed_networks: vec![],
+            denied_networks: vec![],
         },
     ];
 
@@ -383,6 +450,11 @@ fn parse_servers() {
             "failed for {}",
             expected_server.id
         );
+        assert_eq!(
+            server.proxy_networks, expected_server.proxy_networks,
+            "failed for {}",
+            expected_server.id
+        );
         for (listener, expected_listener) in
             server.listeners.into_iter().zip(expected_server.listeners)
         {
@@ -401,10 +473,1201 @@ fn parse_servers() {
                 "failed for {}",
                 expected_server.id
             );
+            assert_eq!(
+                listener.reuse_port, expected_listener.reuse_port,
+                "failed for {}",
+                expected_server.id
+            );
+            assert_eq!(
+                listener.keepalive, expected_listener.keepalive,
+                "failed for {}",
+                expected_server.id
+            );
+            assert_eq!(
+                listener.proxy_protocol, expected_listener.proxy_protocol,
+                "failed for {}",
+                expected_server.id
+            );
         }
     }
 }
 
+#[test]
+fn listener_keepalive() {
+    let listener = Listener {
+        keepalive: Duration::from_secs(120).into(),
+        ..test_listener("127.0.0.1:0")
+    };
+    listener.set_keepalive().unwrap();
+
+    let socket = socket2::SockRef::from(&listener.socket);
+    assert!(socket.keepalive().unwrap());
+    assert_eq!(socket.keepalive_time().unwrap(), Duration::from_secs(120));
+
+    // Unset keepalive leaves the OS default untouched
+    let listener = Listener {
+        keepalive: None,
+        ..listener
+    };
+    listener.set_keepalive().unwrap();
+    assert!(socket2::SockRef::from(&listener.socket)
+        .keepalive()
+        .unwrap());
+}
+
+#[tokio::test]
+async fn listener_bind_retry() {
+    // Fails right away without retries
+    let holder = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let listener = test_listener(&holder.local_addr().unwrap().to_string());
+    assert_eq!(
+        listener
+            .bind(0, Duration::from_millis(10))
+            .await
+            .unwrap_err()
+            .kind(),
+        std::io::ErrorKind::AddrInUse
+    );
+
+    // Succeeds once the address is released
+    let release = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(200));
+        drop(holder);
+    });
+    listener.bind(5, Duration::from_millis(100)).await.unwrap();
+    release.join().unwrap();
+
+    // Other errors are not retried
+    let started = std::time::Instant::now();
+    assert!(test_listener("192.0.2.1:0")
+        .bind(5, Duration::from_secs(10))
+        .await
+        .is_err());
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn listener_effective_options() {
+    let listener = Listener {
+        ttl: 42.into(),
+        linger: Duration::from_secs(5).into(),
+        nodelay: false,
+        ..test_listener("127.0.0.1:0")
+    };
+    listener.set_tcp_options().unwrap();
+    assert_eq!(
+        listener.effective_options().unwrap(),
+        ListenerOptions {
+            ttl: 42,
+            linger: Duration::from_secs(5).into(),
+            nodelay: false,
+        }
+    );
+
+    let listener = Listener {
+        ttl: 64.into(),
+        linger: None,
+        nodelay: true,
+        ..listener
+    };
+    listener.set_tcp_options().unwrap();
+    let options = listener.effective_options().unwrap();
+    assert_eq!(options.ttl, 64);
+    assert!(options.nodelay);
+}
+
+#[cfg(unix)]
+#[test]
+fn listener_unix_socket() {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    let path = std::env::temp_dir().join("stalwart_test_lmtp.sock");
+    let mut config = Config::new(format!(
+        r#"
+[server.listener."lmtp-local"]
+bind = "unix:{}"
+protocol = "lmtp"
+socket.permissions = "0660"
+
+[server.listener."invalid"]
+bind = "unix:"
+protocol = "lmtp"
+"#,
+        path.display()
+    ))
+    .unwrap();
+    let servers = Servers::parse(&mut config);
+    assert!(
+        config.errors.contains_key("server.listener.invalid.bind"),
+        "{:?}",
+        config.errors
+    );
+    config.errors.clear();
+
+    let server = servers
+        .servers
+        .iter()
+        .find(|s| s.id == "lmtp-local")
+        .unwrap();
+    assert!(server.listeners.is_empty());
+    assert_eq!(server.unix_listeners.len(), 1);
+    let listener = &server.unix_listeners[0];
+    assert_eq!(listener.path, path);
+    assert_eq!(listener.permissions, Some(0o660));
+
+    // Bind and verify the socket file
+    listener.bind().unwrap();
+    let metadata = fs::symlink_metadata(&path).unwrap();
+    assert!(metadata.file_type().is_socket());
+    assert_eq!(metadata.permissions().mode() & 0o7777, 0o660);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn listener_backlog() {
+    let mut config = Config::new(
+        r#"
+[server.listener."default"]
+bind = "127.0.0.1:0"
+protocol = "smtp"
+
+[server.listener."custom"]
+bind = "127.0.0.1:0"
+protocol = "smtp"
+socket.backlog = 64
+
+[server.listener."zero"]
+bind = "127.0.0.1:0"
+protocol = "smtp"
+socket.backlog = 0
+
+[server.listener."too-large"]
+bind = "127.0.0.1:0"
+protocol = "smtp"
+socket.backlog = 4294967295
+"#,
+    )
+    .unwrap();
+    let servers = Servers::parse(&mut config).servers;
+    assert_eq!(
+        config.errors.keys().collect::<Vec<_>>(),
+        ["server.listener.zero.socket.backlog"]
+    );
+
+    // Backlogs above the platform maximum are clamped to it
+    assert_eq!(
+        config.warnings.keys().collect::<Vec<_>>(),
+        ["server.listener.too-large.socket.backlog"]
+    );
+
+    let backlog = |id: &str| servers.iter().find(|s| s.id == id).unwrap().listeners[0].backlog;
+    assert_eq!(backlog("custom"), Some(64));
+    assert!(backlog("default").is_some_and(|b| b > 0 && b <= 1024));
+    assert_eq!(backlog("zero"), None);
+    assert!(backlog("too-large").is_some_and(|b| b > 0 && b < u32::MAX));
+}
+
+#[test]
+fn listener_settings() {
+    let mut config = Config::new(
+        r#"
+[server.socket]
+keepalive = "2m"
+send-buffer-size = 65536
+
+[server.listener."valid"]
+bind = ["127.0.0.1:9937", "unix:/tmp/stalwart_test.sock"]
+protocol = "smtp"
+socket.backlog = 16
+socket.ttl = 64
+socket.linger = "1s"
+socket.nodelay = false
+proxy.enable = true
+
+[server.listener."invalid"]
+bind = ["127.0.0.1:0", "localhost"]
+protocol = "smtp"
+socket.backlog = 0
+socket.ttl = "high"
+socket.linger = "5x"
+socket.tos = -1
+socket.permissions = "999"
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        ListenerSettings::from_config(&mut config, "valid").unwrap(),
+        ListenerSettings {
+            bind: vec!["127.0.0.1:9937".parse().unwrap()],
+            unix_bind: vec![PathBuf::from("/tmp/stalwart_test.sock")],
+            backlog: Some(16),
+            ttl: Some(64),
+            linger: Some(Duration::from_secs(1)),
+            nodelay: false,
+            reuse_addr: true,
+            reuse_port: false,
+            keepalive: Some(Duration::from_secs(120)),
+            ipv6_only: None,
+            send_buffer_size: Some(65536),
+            recv_buffer_size: None,
+            tos: None,
+            permissions: None,
+            proxy_protocol: true,
+            accept_rate: None,
+            read_timeout: None,
+            write_timeout: None,
+        }
+    );
+    assert!(config.errors.is_empty(), "{:?}", config.errors);
+
+    // All errors are reported together
+    let errors = ListenerSettings::from_config(&mut config, "invalid").unwrap_err();
+    assert_eq!(
+        errors
+            .iter()
+            .map(|(key, _)| key.as_str())
+            .collect::<Vec<_>>(),
+        [
+            "server.listener.invalid.bind.0001",
+            "server.listener.invalid.socket.backlog",
+            "server.listener.invalid.socket.linger",
+            "server.listener.invalid.socket.permissions",
+            "server.listener.invalid.socket.tos",
+            "server.listener.invalid.socket.ttl",
+        ]
+    );
+
+    // Errors recorded before are not reported again
+    assert!(ListenerSettings::from_config(&mut config, "valid").is_ok());
+
+    // Unless the same keys fail again
+    let errors = ListenerSettings::from_config(&mut config, "invalid").unwrap_err();
+    assert_eq!(errors.len(), 6);
+    assert_eq!(config.errors.len(), 6);
+}
+
+#[test]
+fn listener_multiple_binds() {
+    let mut config = Config::new(
+        r#"
+[server.listener."multi"]
+bind = ["127.0.0.1:9939", "[::1]:9939", "127.0.0.1:9939"]
+protocol = "smtp"
+socket.ttl = 32
+socket.nodelay = false
+"#,
+    )
+    .unwrap();
+    let servers = Servers::parse(&mut config).servers;
+    assert_eq!(
+        config.errors.keys().collect::<Vec<_>>(),
+        ["server.listener.multi.bind.0002"]
+    );
+
+    // Duplicates are dropped, the remaining listeners share the same server and options
+    assert_eq!(servers.len(), 1);
+    let server = &servers[0];
+    assert_eq!(
+        server
+            .listeners
+            .iter()
+            .map(|l| l.addr.to_string())
+            .collect::<Vec<_>>(),
+        ["127.0.0.1:9939", "[::1]:9939"]
+    );
+    for listener in &server.listeners {
+        assert_eq!(listener.ttl, Some(32));
+        assert!(!listener.nodelay);
+    }
+}
+
+#[test]
+fn listener_default_port() {
+    let mut config = Config::new(
+        r#"
+[server.listener."smtp"]
+bind = ["127.0.0.1", "[::1]", "127.0.0.2:2525"]
+protocol = "smtp"
+
+[server.listener."imaps"]
+bind = "127.0.0.1"
+protocol = "imap"
+tls.implicit = true
+
+[server.listener."http"]
+bind = ["127.0.0.1", "127.0.0.1:8080"]
+protocol = "http"
+"#,
+    )
+    .unwrap();
+    let servers = Servers::parse(&mut config);
+    assert_eq!(
+        config.errors.keys().collect::<Vec<_>>(),
+        ["server.listener.http.bind.0000"]
+    );
+    assert_eq!(
+        config.errors["server.listener.http.bind.0000"],
+        ConfigError::Parse {
+            error: "Missing port, http listeners have no standard port".to_string()
+        }
+    );
+
+    // Explicit ports always win over the protocol's standard port
+    for (id, expected) in [
+        ("smtp", &["127.0.0.1:25", "[::1]:25", "127.0.0.2:2525"][..]),
+        ("imaps", &["127.0.0.1:993"][..]),
+        ("http", &["127.0.0.1:8080"][..]),
+    ] {
+        assert_eq!(
+            servers
+                .get(id)
+                .unwrap()
+                .listeners
+                .iter()
+                .map(|l| l.addr.to_string())
+                .collect::<Vec<_>>(),
+            expected,
+            "{id}"
+        );
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn listener_interface_bind() {
+    let mut config = Config::new(
+        r#"
+[server.listener."loopback"]
+bind = ["%if:lo%:9953", "%if:lo:v6%:9953"]
+protocol = "smtp"
+
+[server.listener."missing"]
+bind = "%if:stalwart-none0%:0"
+protocol = "smtp"
+
+[server.listener."invalid"]
+bind = ["%if:lo:v5%:9955", "%if:lo%9955", "%if:%:9955"]
+protocol = "smtp"
+"#,
+    )
+    .unwrap();
+    let servers = Servers::parse(&mut config).servers;
+    let mut errors = config.errors.keys().collect::<Vec<_>>();
+    errors.sort_unstable();
+    assert_eq!(
+        errors,
+        [
+            "server.listener.invalid.bind.0000",
+            "server.listener.invalid.bind.0001",
+            "server.listener.invalid.bind.0002",
+            "server.listener.missing.bind",
+        ]
+    );
+    assert_eq!(
+        config.errors["server.listener.missing.bind"],
+        ConfigError::Build {
+            error: "Network interface \"stalwart-none0\" not found".to_string()
+        }
+    );
+
+    // The loopback interface resolves to its first address of each family
+    let server = servers.iter().find(|s| s.id == "loopback").unwrap();
+    assert_eq!(
+        server
+            .listeners
+            .iter()
+            .map(|l| l.addr.to_string())
+            .collect::<Vec<_>>(),
+        ["127.0.0.1:9953", "[::1]:9953"]
+    );
+}
+
+#[test]
+fn servers_lookup() {
+    let mut config = Config::new(
+        r#"
+[server.listener."smtp"]
+bind = ["127.0.0.1:0"]
+protocol = "smtp"
+
+[server.listener."submission"]
+bind = ["127.0.0.1:9950"]
+protocol = "smtp"
+
+[server.listener."http"]
+bind = ["127.0.0.1:0"]
+protocol = "http"
+"#,
+    )
+    .unwrap();
+    let servers = Servers::parse(&mut config);
+    assert!(config.errors.is_empty(), "{:?}", config.errors);
+
+    assert_eq!(
+        servers
+            .by_protocol(ServerProtocol::Smtp)
+            .map(|s| s.id.as_str())
+            .collect::<Vec<_>>(),
+        ["smtp", "submission"]
+    );
+    assert_eq!(
+        servers
+            .by_protocol(ServerProtocol::Http)
+            .map(|s| s.id.as_str())
+            .collect::<Vec<_>>(),
+        ["http"]
+    );
+    assert_eq!(servers.by_protocol(ServerProtocol::Imap).count(), 0);
+
+    assert_eq!(
+        servers
+            .get("submission")
+            .map(|s| s.listeners[0].addr.port()),
+        Some(9950)
+    );
+    assert!(servers.get("imap").is_none());
+}
+
+#[test]
+fn listener_disabled() {
+    let mut config = Config::new(
+        r#"
+[server.listener."smtp"]
+bind = ["127.0.0.1:0"]
+protocol = "smtp"
+enable = false
+
+[server.listener."imap"]
+bind = ["127.0.0.1:0"]
+protocol = "imap"
+
+[profile.imap-only.server.listener."imap"]
+enable = false
+"#,
+    )
+    .unwrap();
+    let servers = Servers::parse(&mut config).servers;
+    assert!(config.errors.is_empty(), "{:?}", config.errors);
+    assert_eq!(
+        servers.iter().map(|s| s.id.as_str()).collect::<Vec<_>>(),
+        ["imap"]
+    );
+
+    // The flag can be set by a profile
+    config.apply_profile(Some("imap-only"));
+    assert!(Servers::parse(&mut config).servers.is_empty());
+}
+
+#[test]
+fn listener_proxy_trusted_networks() {
+    let mut config = Config::new(
+        r#"
+[server.listener."untrusted"]
+bind = ["127.0.0.1:0"]
+protocol = "smtp"
+proxy.enable = true
+
+[server.listener."trusted"]
+bind = ["127.0.0.1:0"]
+protocol = "smtp"
+proxy.enable = true
+proxy.trusted-networks = ["10.0.0.0/8"]
+"#,
+    )
+    .unwrap();
+
+    // Listeners that would accept PROXY headers from anyone are rejected
+    let servers = Servers::parse(&mut config).servers;
+    assert_eq!(
+        config.errors.keys().collect::<Vec<_>>(),
+        ["server.listener.untrusted.proxy.trusted-networks"]
+    );
+    assert_eq!(
+        servers.iter().map(|s| s.id.as_str()).collect::<Vec<_>>(),
+        ["trusted"]
+    );
+    assert_eq!(
+        servers[0].proxy_networks,
+        [IpAddrMask::parse_value("10.0.0.0/8").unwrap()]
+    );
+}
+
+#[test]
+fn listener_protocol_defaults() {
+    let mut config = Config::new(
+        r#"
+[server.listener."imap"]
+bind = ["127.0.0.1:0"]
+protocol = "imap"
+
+[server.listener."imap-tuned"]
+bind = ["127.0.0.1:0"]
+protocol = "imap"
+socket.nodelay = false
+socket.keepalive = false
+socket.ttl = 32
+socket.linger = "1s"
+
+[server.listener."smtp"]
+bind = ["127.0.0.1:0"]
+protocol = "smtp"
+"#,
+    )
+    .unwrap();
+
+    // Interactive protocols get keepalive probes by default
+    let settings = ListenerSettings::from_config(&mut config, "imap").unwrap();
+    assert!(settings.nodelay);
+    assert_eq!(settings.keepalive, Some(Duration::from_secs(300)));
+    assert_eq!(settings.ttl, None);
+    assert_eq!(settings.linger, None);
+
+    // Explicit options always override the protocol defaults
+    let settings = ListenerSettings::from_config(&mut config, "imap-tuned").unwrap();
+    assert!(!settings.nodelay);
+    assert_eq!(settings.keepalive, None);
+    assert_eq!(settings.ttl, Some(32));
+    assert_eq!(settings.linger, Some(Duration::from_secs(1)));
+
+    let settings = ListenerSettings::from_config(&mut config, "smtp").unwrap();
+    assert!(settings.nodelay);
+    assert_eq!(settings.keepalive, None);
+}
+
+#[test]
+fn listener_traffic_class() {
+    let mut config = Config::new(
+        r#"
+[server.listener."qos"]
+bind = ["127.0.0.1:0", "[::1]:0"]
+protocol = "smtp"
+socket.dscp = 46
+
+[server.listener."qos-tos"]
+bind = ["127.0.0.1:0"]
+protocol = "smtp"
+socket.tos = 16
+
+[server.listener."qos-invalid"]
+bind = ["127.0.0.1:0"]
+protocol = "smtp"
+socket.dscp = 64
+"#,
+    )
+    .unwrap();
+
+    // DSCP values are shifted into the upper six bits of the TOS byte
+    assert_eq!(
+        ListenerSettings::from_config(&mut config, "qos")
+            .unwrap()
+            .tos,
+        Some(184)
+    );
+    assert_eq!(
+        ListenerSettings::from_config(&mut config, "qos-tos")
+            .unwrap()
+            .tos,
+        Some(16)
+    );
+    assert_eq!(
+        ListenerSettings::from_config(&mut config, "qos-invalid")
+            .unwrap_err()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>(),
+        ["server.listener.qos-invalid.socket.dscp"]
+    );
+    config.errors.clear();
+
+    let servers = Servers::parse(&mut config).servers;
+    let server = servers.iter().find(|s| s.id == "qos").unwrap();
+    for listener in &server.listeners {
+        let socket = socket2::SockRef::from(&listener.socket);
+        if listener.addr.is_ipv4() {
+            assert_eq!(socket.tos().unwrap(), 184);
+        } else {
+            #[cfg(target_os = "linux")]
+            assert_eq!(socket.tclass_v6().unwrap(), 184);
+        }
+    }
+}
+
+#[test]
+fn listener_ipv6_only() {
+    let mut config = Config::new(
+        r#"
+[server.listener."dual-stack"]
+bind = ["[::1]:0", "127.0.0.1:0"]
+protocol = "smtp"
+socket.ipv6-only = false
+
+[server.listener."v6-only"]
+bind = "[::1]:0"
+protocol = "smtp"
+socket.ipv6-only = true
+
+[server.listener."default"]
+bind = "[::1]:0"
+protocol = "smtp"
+"#,
+    )
+    .unwrap();
+    let servers = Servers::parse(&mut config).servers;
+    assert!(config.errors.is_empty(), "{:?}", config.errors);
+
+    // Servers are parsed in key order
+    for (server, expected) in
+        servers
+            .iter()
+            .zip([vec![None], vec![Some(false), None], vec![Some(true)]])
+    {
+        let ipv6_only = server.listeners.iter().map(|l| l.ipv6_only);
+        assert_eq!(
+            ipv6_only.collect::<Vec<_>>(),
+            expected,
+            "failed for {}",
+            server.id
+        );
+        for listener in &server.listeners {
+            if let Some(ipv6_only) = listener.ipv6_only {
+                assert_eq!(
+                    socket2::SockRef::from(&listener.socket).only_v6().unwrap(),
+                    ipv6_only
+                );
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn listener_reuse_port() {
+    let mut config = Config::new(
+        r#"
+[server.listener."worker-1"]
+bind = "127.0.0.1:9929"
+protocol = "smtp"
+socket.reuse-port = true
+
+[server.listener."worker-2"]
+bind = "127.0.0.1:9929"
+protocol = "smtp"
+socket.reuse-port = true
+"#,
+    )
+    .unwrap();
+    let servers = Servers::parse(&mut config).servers;
+    assert!(config.errors.is_empty(), "{:?}", config.errors);
+
+    let mut tcp_listeners = Vec::new();
+    for server in servers {
+        for listener in server.listeners {
+            assert!(listener.reuse_port);
+            listener.socket.bind(listener.addr).unwrap();
+            tcp_listeners.push(listener.listen().unwrap());
+        }
+    }
+    assert_eq!(tcp_listeners.len(), 2);
+}
+
+#[test]
+fn listener_sni_certificates() {
+    let (cert, pk) = test_cert_pair();
+
+    let mut config = Config::new(format!(
+        r#"
+[server.listener."smtp".tls.sni."mail.example.com"]
+cert = """{cert}"""
+private-key = """{pk}"""
+
+[server.listener."smtp".tls.sni."*.example.org"]
+cert = """{cert}"""
+private-key = """{pk}"""
+"#
+    ))
+    .unwrap();
+    let sni = parse_sni_certificates(&mut config, "smtp");
+    assert!(config.errors.is_empty(), "{:?}", config.errors);
+    assert_eq!(sni.len(), 2);
+
+    // Hostnames without a match fall back to the global certificates, none here
+    let resolver = CertificateResolver::new(Core::default().into_shared()).with_sni(sni);
+    for (name, expected) in [
+        ("mail.example.com", true),
+        ("MAIL.example.com", true),
+        ("example.com", false),
+        ("imap.example.org", true),
+        ("example.org", false),
+        ("a.b.example.org", false),
+        ("other.net", false),
+    ] {
+        assert_eq!(
+            resolver.resolve_name(Some(name)).is_some(),
+            expected,
+            "failed for {name:?}"
+        );
+    }
+    assert!(resolver.resolve_name(None).is_none());
+}
+
+#[tokio::test]
+async fn listener_tls_min_version() {
+    let (cert, pk) = test_cert_pair();
+    let mut config = Config::new(format!(
+        r#"
+[server.listener."smtp"]
+bind = "127.0.0.1:9932"
+protocol = "smtp"
+tls.min-version = "TLSv1.3"
+
+[server.listener."smtp".tls.sni."localhost"]
+cert = """{cert}"""
+private-key = """{pk}"""
+"#
+    ))
+    .unwrap();
+    let mut servers = Servers::parse(&mut config);
+    servers.parse_tcp_acceptors(&mut config, Core::default().into_shared());
+    assert!(config.errors.is_empty(), "{:?}", config.errors);
+    let acceptor = match servers.tcp_acceptors.remove("smtp").unwrap() {
+        TcpAcceptor::Tls { acceptor, .. } => acceptor,
+        TcpAcceptor::Plain => panic!("Expected TLS acceptor"),
+    };
+
+    // TLS 1.2 clients are rejected
+    for (versions, expected) in [
+        (&[&rustls::version::TLS12][..], false),
+        (rustls::ALL_VERSIONS, true),
+    ] {
+        let client_config = rustls::ClientConfig::builder_with_protocol_versions(versions)
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(DummyVerifier))
+            .with_no_client_auth();
+        let (client, server) = tokio::io::duplex(16384);
+        let acceptor = acceptor.clone();
+        let server = tokio::spawn(async move { acceptor.accept(server).await.is_ok() });
+        let client = TlsConnector::from(Arc::new(client_config))
+            .connect(ServerName::try_from("localhost").unwrap(), client)
+            .await;
+
+        assert_eq!(client.is_ok(), expected);
+        assert_eq!(server.await.unwrap(), expected);
+    }
+
+    // Unknown cipher suites and versions are reported
+    let mut config = Config::new(
+        r#"
+[server.listener."smtp"]
+bind = "127.0.0.1:9932"
+protocol = "smtp"
+tls.min-version = "TLSv1.1"
+tls.cipher-suites = ["TLS13_AES_256_GCM_SHA384", "TLS_RSA_WITH_RC4_128_MD5"]
+"#,
+    )
+    .unwrap();
+    let mut servers = Servers::parse(&mut config);
+    servers.parse_tcp_acceptors(&mut config, Core::default().into_shared());
+    let mut errors = config.errors.keys().cloned().collect::<Vec<_>>();
+    errors.sort();
+    assert_eq!(
+        errors,
+        [
+            "server.listener.smtp.tls.cipher-suites.0001",
+            "server.listener.smtp.tls.min-version"
+        ]
+    );
+}
+
+fn test_listener(addr: &str) -> Listener {
+    Listener {
+        socket: TcpSocket::new_v4().unwrap(),
+        addr: addr.parse().unwrap(),
+        ttl: None,
+        backlog: None,
+        linger: None,
+        nodelay: true,
+        reuse_port: false,
+        keepalive: None,
+        ipv6_only: None,
+        proxy_protocol: false,
+        accept_rate: None,
+        read_timeout: None,
+        write_timeout: None,
+    }
+}
+
+fn test_cert_pair() -> (String, String) {
+    let mut cert_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    cert_path.push("resources");
+    cert_path.push("smtp");
+    cert_path.push("certs");
+    (
+        fs::read_to_string(cert_path.join("tls_cert.pem")).unwrap(),
+        fs::read_to_string(cert_path.join("tls_privatekey.pem")).unwrap(),
+    )
+}
+
+#[derive(Clone)]
+struct IdleSessionManager;
+
+impl SessionManager for IdleSessionManager {
+    async fn handle<T: SessionStream>(self, mut session: SessionData<T>) {
+        // Keep the connection open until the client closes it
+        session.stream.write_all(b"hello\r\n").await.unwrap();
+        let _ = session.stream.read(&mut [0u8; 1]).await;
+    }
+
+    async fn shutdown(&self) {}
+}
+
+#[cfg(unix)]
+#[derive(Clone)]
+struct PeerAddrSessionManager;
+
+#[cfg(unix)]
+impl SessionManager for PeerAddrSessionManager {
+    async fn handle<T: SessionStream>(self, mut session: SessionData<T>) {
+        // Report the remote address seen by the session
+        let addr = SocketAddr::new(session.remote_ip, session.remote_port);
+        session
+            .stream
+            .write_all(format!("{addr}\r\n").as_bytes())
+            .await
+            .unwrap();
+        let _ = session.stream.read(&mut [0u8; 1]).await;
+    }
+
+    async fn shutdown(&self) {}
+}
+
+#[tokio::test]
+async fn listener_max_connections() {
+    let mut config = Config::new(
+        r#"
+[server.listener."smtp"]
+bind = "127.0.0.1:9930"
+protocol = "smtp"
+max-connections = 2
+"#,
+    )
+    .unwrap();
+    let servers = Servers::parse(&mut config);
+    assert!(config.errors.is_empty(), "{:?}", config.errors);
+    servers.bind_and_drop_priv(&mut config).await;
+    assert!(config.errors.is_empty(), "{:?}", config.errors);
+
+    let core = Core::default().into_shared();
+    let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+    for server in servers.servers {
+        assert_eq!(server.max_connections, 2);
+        server.spawn(
+            IdleSessionManager,
+            core.clone(),
+            TcpAcceptor::Plain,
+            shutdown_rx.clone(),
+        );
+    }
+
+    // The first connections are accepted
+    let mut clients = Vec::new();
+    for _ in 0..2 {
+        let mut stream = TcpStream::connect("127.0.0.1:9930").await.unwrap();
+        let mut buf = vec![0u8; 7];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, b"hello\r\n");
+        clients.push(stream);
+    }
+
+    // The last one is rejected
+    let mut stream = TcpStream::connect("127.0.0.1:9930").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("421 "), "{response:?}");
+}
+
+#[tokio::test]
+async fn listener_io_timeouts() {
+    let mut config = Config::new(
+        r#"
+[server.socket]
+keepalive = "2m"
+
+[server.listener."smtp"]
+bind = "127.0.0.1:0"
+protocol = "smtp"
+read-timeout = "30s"
+write-timeout = "1m"
+"#,
+    )
+    .unwrap();
+    let settings = ListenerSettings::from_config(&mut config, "smtp").unwrap();
+    assert_eq!(settings.read_timeout, Some(Duration::from_secs(30)));
+    assert_eq!(settings.write_timeout, Some(Duration::from_secs(60)));
+    assert_eq!(settings.keepalive, Some(Duration::from_secs(120)));
+
+    // A client that never sends anything is timed out
+    let (client, server) = tokio::io::duplex(16);
+    let mut stream = TimeoutStream::new(server, Duration::from_millis(100).into(), None);
+    let err = stream.read(&mut [0u8; 1]).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+
+    // Progress restarts the timer
+    let (mut client, server) = (client, stream.into_inner());
+    let mut stream = TimeoutStream::new(server, Duration::from_millis(300).into(), None);
+    let writer = tokio::spawn(async move {
+        for byte in b"abc" {
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            client.write_all(&[*byte]).await.unwrap();
+        }
+        client
+    });
+    let mut buf = [0u8; 3];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"abc");
+    let _client = writer.await.unwrap();
+
+    // A client that stops reading is timed out on write
+    let (_client, server) = tokio::io::duplex(16);
+    let mut stream = TimeoutStream::new(server, None, Duration::from_millis(100).into());
+    let err = stream.write_all(&[0u8; 1024]).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+}
+
+#[tokio::test]
+async fn listener_accept_rate() {
+    let mut config = Config::new(
+        r#"
+[server.listener."smtp"]
+bind = "127.0.0.1:9948"
+protocol = "smtp"
+accept-rate = "3/1m"
+"#,
+    )
+    .unwrap();
+    assert_eq!(
+        ListenerSettings::from_config(&mut config, "smtp")
+            .unwrap()
+            .accept_rate,
+        Some(Rate {
+            requests: 3,
+            period: Duration::from_secs(60),
+        })
+    );
+    let servers = Servers::parse(&mut config);
+    servers.bind_and_drop_priv(&mut config).await;
+    assert!(config.errors.is_empty(), "{:?}", config.errors);
+
+    let core = Core::default().into_shared();
+    let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+    for server in servers.servers {
+        server.spawn(
+            IdleSessionManager,
+            core.clone(),
+            TcpAcceptor::Plain,
+            shutdown_rx.clone(),
+        );
+    }
+
+    // Connections within the rate are accepted
+    let mut clients = Vec::new();
+    for _ in 0..3 {
+        let mut stream = TcpStream::connect("127.0.0.1:9948").await.unwrap();
+        let mut buf = vec![0u8; 7];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, b"hello\r\n");
+        clients.push(stream);
+    }
+
+    // The rest of the burst is closed without a response
+    for _ in 0..3 {
+        let mut stream = TcpStream::connect("127.0.0.1:9948").await.unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response).await;
+        assert!(response.is_empty(), "{response:?}");
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn listener_max_connections_per_ip() {
+    let mut config = Config::new(
+        r#"
+[server.listener."smtp"]
+bind = "127.0.0.1:9931"
+protocol = "smtp"
+max-connections = 10
+max-connections-per-ip = 2
+"#,
+    )
+    .unwrap();
+    let servers = Servers::parse(&mut config);
+    assert!(config.errors.is_empty(), "{:?}", config.errors);
+    servers.bind_and_drop_priv(&mut config).await;
+    assert!(config.errors.is_empty(), "{:?}", config.errors);
+
+    let core = Core::default().into_shared();
+    let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+    for server in servers.servers {
+        assert_eq!(server.max_connections_per_ip, Some(2));
+        server.spawn(
+            IdleSessionManager,
+            core.clone(),
+            TcpAcceptor::Plain,
+            shutdown_rx.clone(),
+        );
+    }
+
+    // The noisy IP uses up its connections
+    let mut noisy = Vec::new();
+    for _ in 0..2 {
+        noisy.push(connect_from("127.0.0.1", "127.0.0.1:9931").await.unwrap());
+    }
+    assert!(connect_from("127.0.0.1", "127.0.0.1:9931")
+        .await
+        .unwrap_err()
+        .starts_with("421 "));
+
+    // Other clients are unaffected
+    let _other = connect_from("127.0.0.2", "127.0.0.1:9931").await.unwrap();
+
+    // Closing a connection frees up a slot
+    drop(noisy.pop());
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    noisy.push(connect_from("127.0.0.1", "127.0.0.1:9931").await.unwrap());
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn listener_access_lists() {
+    let mut config = Config::new(
+        r#"
+[server.listener."invalid"]
+bind = "127.0.0.1:0"
+protocol = "smtp"
+allow = ["127.0.0.1", "not-a-network"]
+"#,
+    )
+    .unwrap();
+    Servers::parse(&mut config);
+    assert_eq!(
+        config.errors.keys().collect::<Vec<_>>(),
+        ["server.listener.invalid.allow.0001"]
+    );
+
+    let mut config = Config::new(
+        r#"
+[server]
+allow = ["10.0.0.0/8"]
+deny = "127.0.0.4"
+
+[server.listener."managesieve"]
+bind = "127.0.0.1:9956"
+protocol = "managesieve"
+allow = ["127.0.0.0/24"]
+deny = ["127.0.0.3"]
+"#,
+    )
+    .unwrap();
+    let servers = Servers::parse(&mut config);
+    assert!(config.errors.is_empty(), "{:?}", config.errors);
+    servers.bind_and_drop_priv(&mut config).await;
+    assert!(config.errors.is_empty(), "{:?}", config.errors);
+
+    let core = Core::default().into_shared();
+    let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+    for server in servers.servers {
+        assert_eq!(server.allowed_networks.len(), 1);
+        assert_eq!(server.denied_networks.len(), 2);
+        server.spawn(
+            IdleSessionManager,
+            core.clone(),
+            TcpAcceptor::Plain,
+            shutdown_rx.clone(),
+        );
+    }
+
+    // Denied and unlisted peers are dropped before any protocol bytes are sent
+    let addr = "127.0.0.1:9956";
+    assert!(connect_from("127.0.0.1", addr).await.is_ok());
+    assert_eq!(connect_from("127.0.0.3", addr).await.unwrap_err(), "");
+    assert_eq!(connect_from("127.0.1.1", addr).await.unwrap_err(), "");
+
+    // Listener allow lists replace the server-wide one, while deny lists are merged
+    assert_eq!(connect_from("127.0.0.4", addr).await.unwrap_err(), "");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn listener_unix_socket_peers() {
+    let path = std::env::temp_dir().join("stalwart_test_acl.sock");
+    let mut config = Config::new(format!(
+        r#"
+[server.listener."lmtp-local"]
+bind = "unix:{}"
+protocol = "lmtp"
+deny = ["0.0.0.0", "127.0.0.1"]
+max-connections-per-ip = 1
+"#,
+        path.display()
+    ))
+    .unwrap();
+    let servers = Servers::parse(&mut config);
+    assert!(config.errors.is_empty(), "{:?}", config.errors);
+
+    // Access lists are reported as not applying instead of being ignored silently
+    assert_eq!(
+        config.warnings.keys().collect::<Vec<_>>(),
+        ["server.listener.lmtp-local.bind"]
+    );
+    servers.bind_and_drop_priv(&mut config).await;
+    assert!(config.errors.is_empty(), "{:?}", config.errors);
+
+    let core = Core::default().into_shared();
+    let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+    for server in servers.servers {
+        server.spawn(
+            PeerAddrSessionManager,
+            core.clone(),
+            TcpAcceptor::Plain,
+            shutdown_rx.clone(),
+        );
+    }
+
+    // Unix peers are accepted regardless of the deny list and the per-IP limit
+    let mut clients = Vec::new();
+    for _ in 0..2 {
+        let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        let expected = format!("{UNIX_PEER_ADDR}\r\n");
+        let mut buf = vec![0u8; expected.len()];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, expected.as_bytes());
+        clients.push(stream);
+    }
+    fs::remove_file(&path).unwrap();
+}
+
+#[cfg(target_os = "linux")]
+async fn connect_from(ip: &str, addr: &str) -> Result<TcpStream, String> {
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.bind(format!("{ip}:0").parse().unwrap()).unwrap();
+    let mut stream = socket.connect(addr.parse().unwrap()).await.unwrap();
+    let mut response = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !response.ends_with(b"\r\n") {
+        match stream.read(&mut buf).await.unwrap() {
+            0 => break,
+            n => response.extend_from_slice(&buf[..n]),
+        }
+    }
+    let response = String::from_utf8(response).unwrap();
+    if response == "hello\r\n" {
+        Ok(stream)
+    } else {
+        Err(response)
+    }
+}
+
 #[tokio::test]
 async fn eval_if() {
     let mut file = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
@@ -502,6 +1765,62 @@ async fn eval_dynvalue() {
     }
 }
 
+#[tokio::test]
+async fn spam_header_names() {
+    let mut config = Config::new(
+        r#"
+[lookup.spam-config]
+header-status = "X-Spam-Status"
+header-result = "X-Spam-Result, Bad:Name, Bad Name, X\tTab"
+"#,
+    )
+    .unwrap();
+    let stores = Stores::parse_all(&mut config).await;
+    Scripting::parse(&mut config, &stores).await;
+
+    assert_eq!(
+        config.warnings.keys().collect::<Vec<_>>(),
+        vec!["lookup.spam-config.header-result"]
+    );
+    assert_eq!(
+        config.warnings["lookup.spam-config.header-result"],
+        ConfigWarning::Build {
+            error: concat!(
+                "Invalid header names \"Bad:Name\", \"Bad Name\", \"X\\tTab\", ",
+                "these headers will not be added"
+            )
+            .to_string()
+        }
+    );
+}
+
+#[tokio::test]
+async fn spam_score_precision() {
+    for (value, is_valid) in [
+        ("", true),
+        ("0", true),
+        ("6", true),
+        ("7", false),
+        ("two", false),
+    ] {
+        let mut config = Config::new(format!(
+            "[lookup.spam-config]\nscore-precision = \"{value}\"\n"
+        ))
+        .unwrap();
+        let stores = Stores::parse_all(&mut config).await;
+        Scripting::parse(&mut config, &stores).await;
+
+        assert_eq!(
+            config
+                .warnings
+                .contains_key("lookup.spam-config.score-precision"),
+            !is_valid,
+            "failed for {value:?}: {:?}",
+            config.warnings
+        );
+    }
+}
+
 impl ResolveVariable for TestEnvelope {
     fn resolve_variable(&self, variable: u32) -> Variable<'_> {
         match variable {
@@ -16,13 +16,14 @@ use common::{
     Core,
 };
 use mail_auth::{dmarc::Policy, DkimResult, DmarcResult, IprevResult, SpfResult, MX};
+use nlp::bayes::Weights;
 use sieve::runtime::Variable;
 use smtp::{
-    core::{Inner, Session, SessionAddress},
+    core::{Inner, Session, SessionAddress, SMTP},
     inbound::AuthResult,
     scripts::ScriptResult,
 };
-use store::Stores;
+use store::{write::key::KeySerializer, Stores, U64_LEN};
 use tokio::runtime::Handle;
 use utils::config::Config;
 
@@ -33,19 +34,42 @@ const CONFIG: &str = r#"
 is-spam = "X-Spam-Status: Yes"
 
 [lookup.spam-config]
+spam-mode = "enforce"
 add-spam = true
+header-status = "X-Spam-Status"
+add-spam-score = false
 add-spam-result = true
+header-result = "X-Spam-Result"
+result-format = "text"
+result-fold-width = ""
+result-fold-indent = "\t"
+result-hidden-prefixes = ""
+result-descriptions = ""
+auth-results = false
+skip-authenticated = false
+trusted-networks = ""
 learn-enable = true
 #learn-balance = "0.9"
 learn-balance = "0.0"
+learn-forced-weight = "2"
 learn-ham-replies = true
 learn-ham-threshold = "-0.5"
 learn-spam-threshold = "6.0"
 threshold-spam = "5.0"
 threshold-discard = 0
 threshold-reject = 0
+threshold-quarantine = 0
+threshold-inclusive = true
+score-early-reject = false
+threshold-lookup = ""
 directory = ""
 lookup = ""
+score-lookup = ""
+score-groups = ""
+score-group-limits = ""
+score-precision = ""
+score-clamp = 0
+log-scores = false
 
 [session.rcpt]
 relay = true
@@ -93,6 +117,12 @@ path = "{PATH}/test_antispam.db"
                 "hta" = "BAD|NZ" }
 "spam-trap" = {"spamtrap@*"}
 "spam-allow" = {"stalw.art"}
+"spam-test-descriptions" = {"TEST_A" = "First test tag", "TEST_B" = "Second\r\n tag\t—", "X_ONE" = "Internal tag"}
+"spam-test-groups" = {"TEST_A" = "test", "TEST_B" = "test"}
+"spam-test-group-limits" = {"test" = "2.5"}
+"spam-test-trusted" = {"10.0.0.1"}
+"spam-test-thresholds" = {"strict@foobar.org" = "3|2", "lenient@foobar.org" = "10", "nodiscard@foobar.org" = "|0"}
+"spam-test-scores" = {"TEST_A" = "1.5", "TEST_B" = "2.0", "TEST_C" = "-1.0", "TEST_ZERO" = "0.0", "X_ZERO" = "0.0", "X_ONE" = "1.0", "TEST_ZALLOW" = "allow", "TEST_ZREJECT" = "reject", "TEST_ZDISCARD" = "discard", "TEST_W_*" = "1.0", "TEST_W_LONG*" = "3.0", "TEST_W_LONG_EXACT" = "0.5"}
 
 [resolver]
 public-suffix = "file://{LIST_PATH}/public-suffix.dat"
@@ -100,6 +130,88 @@ public-suffix = "file://{LIST_PATH}/public-suffix.dat"
 [sieve.trusted.scripts]
 "#;
 
+// Spam configuration overrides used by the verdict tests, shared by all variants
+const VERDICT_CONFIG: &str = r#"
+learn-enable = false
+score-lookup = "spam-test-scores"
+"#;
+
+const VERDICT_VARIANTS: &[(&str, &str)] = &[
+    ("text", ""),
+    (
+        "autolearn",
+        "learn-enable = true\nlearn-spam-threshold = 6\nlearn-ham-threshold = -0.5",
+    ),
+    (
+        "report",
+        "spam-mode = \"report\"\nthreshold-reject = 8\nthreshold-discard = 6",
+    ),
+    ("log_scores", "log-scores = true"),
+    ("fold_none", "result-fold-width = 0"),
+    ("fold_40", "result-fold-width = 40"),
+    ("fold_spaces", "result-fold-indent = \"  \""),
+    ("fold_invalid", "result-fold-indent = \" \\r\\n \""),
+    ("json", "result-format = \"json\""),
+    (
+        "headers",
+        concat!(
+            "header-status = \"X-Spam-Status,X-Custom-Status\"\n",
+            "header-result = \"X-Spam-Result, X-Custom-Result ,Bad:Name,Bad Name,\""
+        ),
+    ),
+    (
+        "verbose",
+        "result-descriptions = \"spam-test-descriptions\"",
+    ),
+    ("score_only", "add-spam = false\nadd-spam-score = true"),
+    ("score", "add-spam-score = true"),
+    ("reject", "threshold-reject = 3"),
+    (
+        "early_reject",
+        "threshold-reject = 3\nscore-early-reject = true",
+    ),
+    ("inclusive", "threshold-reject = 8\nthreshold-discard = 6"),
+    (
+        "strict",
+        "threshold-reject = 8\nthreshold-discard = 6\nthreshold-inclusive = false",
+    ),
+    ("precision_0", "score-precision = 0"),
+    (
+        "precision_3",
+        "score-precision = 3\nresult-format = \"json\"",
+    ),
+    ("precision_9", "score-precision = 9"),
+    ("hidden", "result-hidden-prefixes = \"X_,TEST_Z\""),
+    (
+        "groups",
+        concat!(
+            "score-groups = \"spam-test-groups\"\n",
+            "score-group-limits = \"spam-test-group-limits\"\n",
+            "threshold-spam = 3"
+        ),
+    ),
+    (
+        "skip_auth",
+        concat!(
+            "skip-authenticated = true\n",
+            "trusted-networks = \"spam-test-trusted\""
+        ),
+    ),
+    (
+        "thresholds",
+        concat!(
+            "threshold-lookup = \"spam-test-thresholds\"\n",
+            "threshold-reject = 20\n",
+            "threshold-discard = 8"
+        ),
+    ),
+    (
+        "json_no_result",
+        "result-format = \"json\"\nadd-spam-result = false",
+    ),
+    ("auth_results", "auth-results = true"),
+];
+
 #[tokio::test(flavor = "multi_thread")]
 async fn antispam() {
     /*tracing::subscriber::set_global_default(
@@ -193,10 +305,47 @@ async fn antispam() {
                 .as_str();
     }
 
+    // Verdict tests run the scoring and epilogue scripts on injected tags,
+    // each variant reading its own copy of the spam configuration
+    let verdict_tests = fs::read_to_string(
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("resources")
+            .join("smtp")
+            .join("antispam")
+            .join("verdict.test"),
+    )
+    .unwrap();
+    let mut verdict_tags = verdict_tests
+        .lines()
+        .filter_map(|line| line.strip_prefix("tags "))
+        .flat_map(|tags| tags.split_ascii_whitespace())
+        .collect::<Vec<_>>();
+    verdict_tags.sort_unstable();
+    verdict_tags.dedup();
+    let mut verdict_prelude =
+        "if eval \"is_number(env.score)\" {let \"score\" \"env.score\";}\n".to_string();
+    for tag in verdict_tags {
+        verdict_prelude.push_str(&format!(
+            "if eval \"contains(env.tags, '{tag}')\" {{let \"t.{tag}\" \"1\";}}\n"
+        ));
+    }
+    let script_scores = fs::read_to_string(base_path.join("scores.sieve")).unwrap();
+    let script_epilogue = fs::read_to_string(base_path.join("epilogue.sieve")).unwrap();
+    let mut verdict_configs = String::new();
+    for (variant, overrides) in VERDICT_VARIANTS {
+        let lookup = format!("spam-config-{variant}");
+        config.push_str(&format!(
+            "verdict_{variant}.contents = '''{}\n{script_prelude}\n{verdict_prelude}\n{script_scores}\n{script_epilogue}\n'''\n",
+            script_config.replace("'spam-config'", &format!("'{lookup}'"))
+        ));
+        verdict_configs.push_str(&spam_config_variant(&lookup, &[VERDICT_CONFIG, overrides]));
+    }
+
     config.push_str(&format!(
         "combined.contents = '''{all_scripts}\n'''\n[lookup]\n"
     ));
     config.push_str(&scores);
+    config.push_str(&verdict_configs);
 
     // Parse config
     let mut config = Config::new(&config).unwrap();
@@ -268,12 +417,11 @@ async fn antispam() {
         .join("smtp")
         .join("antispam");
     let span = tracing::info_span!("sieve_antispam");
-    for &test_name in tests.iter().chain(&["combined"]) {
+    for &test_name in tests.iter().chain(&["verdict", "combined"]) {
         /*if test_name != "combined" {
             continue;
         }*/
         println!("===== {test_name} =====");
-        let script = core.core.sieve.scripts.get(test_name).cloned().unwrap();
 
         let contents = fs::read_to_string(base_path.join(format!("{test_name}.test"))).unwrap();
         let mut lines = contents.lines();
@@ -285,6 +433,9 @@ async fn antispam() {
             let mut variables: HashMap<String, Variable> = HashMap::new();
             let mut expected_variables = AHashMap::new();
             let mut expected_headers = AHashMap::new();
+            let mut expected_action = None;
+            let mut expected_learn = None;
+            let mut script_name = test_name.to_string();
 
             // Build session
             let mut session = Session::test(core.clone());
@@ -377,12 +528,35 @@ async fn antispam() {
                         }
                         "expect_header" => {
                             if let Some((header, value)) = value.split_once(' ') {
-                                expected_headers
-                                    .insert(header.to_string(), value.trim().to_string());
+                                expected_headers.insert(
+                                    header.to_string(),
+                                    value.trim().replace("\\r\\n", "\r\n").replace("\\t", "\t"),
+                                );
                             } else {
                                 expected_headers.insert(value.to_string(), String::new());
                             }
                         }
+                        "expect_action" => {
+                            expected_action = Some(value.to_string());
+                        }
+                        "expect_learn" => {
+                            expected_learn = Some(value.to_string());
+                        }
+                        "config" => {
+                            script_name = format!("{test_name}_{value}");
+                        }
+                        "tags" => {
+                            let tags = value
+                                .split_ascii_whitespace()
+                                .map(|tag| Variable::from(tag.to_string()))
+                                .collect::<Vec<_>>();
+                            expected_variables.extend(
+                                tags.iter().map(|tag| {
+                                    (tag.to_string().to_lowercase(), Variable::Integer(1))
+                                }),
+                            );
+                            variables.insert(param.to_string(), tags.into());
+                        }
                         "score" | "final_score" => {
                             variables
                                 .insert(param.to_string(), value.parse::<f64>().unwrap().into());
@@ -420,16 +594,26 @@ async fn antispam() {
             }
 
             // Run script
+            let learns_before = bayes_learns(&core).await;
             let handle = Handle::current();
             let span = span.clone();
             let core_ = core.clone();
-            let script = script.clone();
+            let script = core
+                .core
+                .sieve
+                .scripts
+                .get(script_name.as_str())
+                .cloned()
+                .unwrap();
             match core
                 .spawn_worker(move || core_.run_script_blocking(script, params, handle, span))
                 .await
                 .unwrap()
             {
                 ScriptResult::Accept { modifications } => {
+                    if let Some(action) = &expected_action {
+                        panic!("Expected {action}, got accept {modifications:?}");
+                    }
                     if modifications.len() != expected_headers.len() {
                         panic!(
                             "Expected {:?} headers, got {:?}",
@@ -455,7 +639,11 @@ async fn antispam() {
                         }
                     }
                 }
-                ScriptResult::Reject(message) => panic!("{}", message),
+                ScriptResult::Reject(message) => {
+                    if expected_action.as_deref() != Some("reject") {
+                        panic!("{}", message)
+                    }
+                }
                 ScriptResult::Replace {
                     message,
                     modifications,
@@ -464,12 +652,113 @@ async fn antispam() {
                     String::from_utf8_lossy(&message),
                     modifications
                 ),
-                ScriptResult::Discard => println!("Discard"),
+                ScriptResult::Discard => {
+                    if matches!(&expected_action, Some(action) if action != "discard") {
+                        panic!("Expected {action:?}, got discard", action = expected_action);
+                    }
+                    println!("Discard")
+                }
             }
+
+            // Automatic training only happens when expected
+            if let Some(expected_learn) = &expected_learn {
+                let learns = bayes_learns(&core).await;
+                assert_eq!(
+                    (
+                        learns.spam - learns_before.spam,
+                        learns.ham - learns_before.ham
+                    ),
+                    match expected_learn.as_str() {
+                        "spam" => (1, 0),
+                        "ham" => (0, 1),
+                        "none" => (0, 0),
+                        _ => panic!("Invalid expect_learn {expected_learn:?}"),
+                    },
+                    "unexpected training for {expected_learn:?}"
+                );
+            }
+        }
+
+        // Spam trap hits are learned with the forced weight, regardless of the balance
+        if test_name == "spamtrap" {
+            assert_eq!(bayes_learns(&core).await.spam, 20);
         }
     }
 }
 
+const BAYES_RATE_CONFIG: &str = r#"
+[spam.bayes]
+auto-learn-rate = "3/1h"
+
+[sieve.trusted]
+hostname = "mx.foobar.org"
+no-capability-check = true
+
+[storage]
+data = "spamdb"
+lookup = "spamdb"
+blob = "spamdb"
+fts = "spamdb"
+
+[store."spamdb"]
+type = "sqlite"
+path = "{PATH}/test_bayes_rate.db"
+
+[sieve.trusted.scripts.burst]
+contents = '''
+let "i" "10";
+while "i > 0" {
+    let "i" "i - 1";
+    eval "bayes_autolearn('', 'cheap watches offer number ' + i, true)";
+}
+eval "bayes_train('', 'explicit training is never rate limited', true)";
+'''
+"#;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bayes_autolearn_rate() {
+    let tmp_dir = TempDir::new("smtp_bayes_rate_test", true);
+    let mut config = Config::new(
+        BAYES_RATE_CONFIG.replace("{PATH}", tmp_dir.temp_dir.as_path().to_str().unwrap()),
+    )
+    .unwrap();
+    let stores = Stores::parse_all(&mut config).await;
+    let core = Core::parse(&mut config, stores, Default::default()).await;
+    let core = build_smtp(core, Inner::default());
+
+    // Simulate a burst of automatic training operations
+    let script = core.core.sieve.scripts.get("burst").cloned().unwrap();
+    let params = Session::test(core.clone())
+        .build_script_parameters("data")
+        .with_message(Arc::new(b"Subject: test\r\n\r\ntest\r\n".to_vec()));
+    let handle = Handle::current();
+    let span = tracing::info_span!("bayes_autolearn_rate");
+    let core_ = core.clone();
+    core.spawn_worker(move || core_.run_script_blocking(script, params, handle, span))
+        .await
+        .unwrap();
+
+    // Only the allowed number of automatic operations plus the explicit one are learned
+    assert_eq!(bayes_learns(&core).await.spam, 4);
+}
+
+// Number of messages learned by the global bayes model
+async fn bayes_learns(core: &SMTP) -> Weights {
+    Weights::from(
+        core.core
+            .storage
+            .lookup
+            .counter_get(
+                KeySerializer::new(U64_LEN)
+                    .write(0u64)
+                    .write(0u64)
+                    .finalize(),
+            )
+            .await
+            .unwrap(),
+    )
+}
+
 #[test]
 fn html_tokens() {
     for (input, expected) in [
@@ -771,3 +1060,33 @@ impl ParseConfigValue for Policy {
         }
     }
 }
+
+fn spam_config_variant(name: &str, overrides: &[&str]) -> String {
+    let (_, base) = CONFIG.split_once("[lookup.spam-config]\n").unwrap();
+    let (base, _) = base.split_once("\n\n").unwrap();
+    let mut entries = base
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once(" = "))
+        .collect::<Vec<_>>();
+    for (key, value) in overrides
+        .iter()
+        .flat_map(|overrides| overrides.lines())
+        .filter_map(|line| line.split_once(" = "))
+    {
+        if let Some(entry) = entries.iter_mut().find(|(k, _)| *k == key) {
+            entry.1 = value;
+        } else {
+            entries.push((key, value));
+        }
+    }
+
+    format!(
+        "\"{name}\" = {{\n{}\n}}\n",
+        entries
+            .iter()
+            .map(|(key, value)| format!("\"{key}\" = {value}"))
+            .collect::<Vec<_>>()
+            .join(",\n")
+    )
+}
@@ -27,7 +27,7 @@ use common::{
 };
 use jmap::{api::JmapSessionManager, JMAP};
 use store::{BlobStore, Store, Stores};
-use tokio::sync::{mpsc, watch};
+use tokio::sync::mpsc;
 
 use ::smtp::core::{Inner, Session, SmtpInstance, SmtpSessionManager, SMTP};
 use utils::config::Config;
@@ -134,7 +134,7 @@ impl TestServer {
         }
     }
 
-    pub async fn start(&self, protocols: &[ServerProtocol]) -> watch::Sender<bool> {
+    pub async fn start(&self, protocols: &[ServerProtocol]) -> Servers {
         // Spawn listeners
         let mut config = Config::new(CONFIG).unwrap();
         let mut servers = Servers::parse(&mut config);
@@ -146,7 +146,7 @@ impl TestServer {
             .retain(|server| protocols.contains(&server.protocol));
 
         // Start servers
-        servers.bind_and_drop_priv(&mut config);
+        servers.bind_and_drop_priv(&mut config).await;
         let instance = self.instance.clone();
         let smtp_manager = SmtpSessionManager::new(instance.clone());
         let jmap = JMAP::init(
@@ -159,27 +159,27 @@ impl TestServer {
         let jmap_manager = JmapSessionManager::new(jmap);
         config.assert_no_errors();
 
+        servers.spawn(|server, acceptor, shutdown_rx| {
+            match &server.protocol {
+                ServerProtocol::Smtp | ServerProtocol::Lmtp => server.spawn(
+                    smtp_manager.clone(),
+                    instance.core.clone(),
+                    acceptor,
+                    shutdown_rx,
+                ),
+                ServerProtocol::Http => server.spawn(
+                    jmap_manager.clone(),
+                    instance.core.clone(),
+                    acceptor,
+                    shutdown_rx,
+                ),
+                ServerProtocol::Imap | ServerProtocol::ManageSieve | ServerProtocol::Pop3 => {
+                    unreachable!()
+                }
+            };
+        });
+
         servers
-            .spawn(|server, acceptor, shutdown_rx| {
-                match &server.protocol {
-                    ServerProtocol::Smtp | ServerProtocol::Lmtp => server.spawn(
-                        smtp_manager.clone(),
-                        instance.core.clone(),
-                        acceptor,
-                        shutdown_rx,
-                    ),
-                    ServerProtocol::Http => server.spawn(
-                        jmap_manager.clone(),
-                        instance.core.clone(),
-                        acceptor,
-                        shutdown_rx,
-                    ),
-                    ServerProtocol::Imap | ServerProtocol::ManageSieve => {
-                        unreachable!()
-                    }
-                };
-            })
-            .0
     }
 
     pub fn new_session(&self) -> Session<DummyIo> {
@@ -373,8 +373,12 @@ impl TestServerInstance for ServerInstance {
                 implicit: false,
             },
             limiter: ConcurrencyLimiter::new(100),
+            ip_limiter: None,
             shutdown_rx,
             proxy_networks: vec![],
+            allowed_networks: vec![],
+            denied_networks: vec![],
+            max_request_size: None,
         }
     }
 }